@@ -0,0 +1,48 @@
+use std::fs;
+use std::mem;
+use crate::{Error, Result};
+
+/// File listing CPUs currently online, in list format like "0-3,6".
+const CPU_ONLINE_PATH: &str = "/sys/devices/system/cpu/online";
+
+/// Parse CPU list format used by sysfs and taskset, e.g. "0-3,6".
+#[inline]
+pub fn parse_cpu_list(s: &str) -> Result<Vec<usize>> {
+    let mut cpus = vec![];
+    for item in s.trim().split(',') {
+        let parse = |s: &str| s.trim().parse::<usize>().map_err(|_| Error::InvalidCpuList);
+        if let Some((from, to)) = item.split_once('-') {
+            let (from, to) = (parse(from)?, parse(to)?);
+            if from > to {
+                return Err(Error::InvalidCpuList)
+            }
+            cpus.extend(from..=to);
+        } else {
+            cpus.push(parse(item)?);
+        }
+    }
+    Ok(cpus)
+}
+
+/// Returns CPUs currently online.
+#[inline]
+pub fn online_cpus() -> Result<Vec<usize>> {
+    parse_cpu_list(&fs::read_to_string(CPU_ONLINE_PATH)?)
+}
+
+/// Pin current thread to given CPU, which must be online.
+#[inline]
+pub fn pin_cpu(cpu: usize) -> Result<()> {
+    if !online_cpus()?.contains(&cpu) {
+        return Err(Error::CpuNotOnline(cpu))
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        // pid 0 means calling thread
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(Error::FailSetAffinity(errno::errno().0))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::{Record, Result};
+
+/// Fields identifying the scenario of a record, each must be equal or
+/// missing in both records, e.g. `svr_cpu` only recorded by `bench`.
+const KEY_FIELDS: [&str; 10] = ["transport", "threads", "value", "size", "window", "rate", "spin_only", "sqpoll", "cpu", "svr_cpu"];
+
+/// Metrics compared with baseline, and whether higher is better.
+const METRICS: [(&str, bool); 3] = [("throughput", true), ("p50_ns", false), ("p99_ns", false)];
+
+/// Baseline is a set of records saved by previous run.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    records: Vec<Record>,
+}
+
+impl Baseline {
+    /// Save records as CSV, header is written again with a blank line
+    /// before it whenever fields change.
+    #[inline]
+    pub fn save<P: AsRef<Path>>(path: P, records: &[Record]) -> Result<()> {
+        let mut s = String::new();
+        let mut header = String::new();
+        for record in records {
+            let h = record.csv_header();
+            if h != header {
+                if !header.is_empty() {
+                    s.push('\n');
+                }
+                s.push_str(&h);
+                s.push('\n');
+                header = h;
+            }
+            s.push_str(&record.to_csv());
+            s.push('\n');
+        }
+        fs::write(path, s)?;
+        Ok(())
+    }
+
+    /// Load records saved by [`Baseline::save`], or CSV output of `cli`.
+    #[inline]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let s = fs::read_to_string(path)?;
+        let mut records = vec![];
+        for block in s.split("\n\n") {
+            let mut lines = block.lines().filter(|line| !line.is_empty());
+            if let Some(header) = lines.next() {
+                records.extend(lines.map(|row| Record::from_csv(header, row)));
+            }
+        }
+        Ok(Baseline{records})
+    }
+
+    /// Compare record with the baseline record of same scenario, returns
+    /// None if not found. A metric regresses if it becomes worse by more
+    /// than `threshold` percent. Metric of zero baseline is skipped, as
+    /// its change is undefined.
+    #[inline]
+    pub fn compare(&self, record: &Record, threshold: f64) -> Option<Comparison> {
+        let same_scenario = |r: &Record| KEY_FIELDS.iter().all(|&name| match (r.get(name), record.get(name)) {
+            (Some(a), Some(b)) => a.to_string() == b.to_string(),
+            (None, None) => true,
+            _ => false,
+        });
+        let base = self.records.iter().find(|r| same_scenario(r))?;
+        let deltas = METRICS.iter()
+            .filter_map(|&(metric, higher_better)| {
+                let (baseline, current) = (base.metric(metric)?, record.metric(metric)?);
+                if baseline == 0.0 || !baseline.is_finite() {
+                    return None
+                }
+                let change = (current - baseline) / baseline * 100.0;
+                let worse = if higher_better { -change } else { change };
+                Some(Delta{metric, baseline, current, change, regressed: worse > threshold})
+            })
+            .collect();
+        Some(Comparison{deltas})
+    }
+}
+
+/// Change of one metric from baseline.
+#[derive(Debug, Clone, Copy)]
+pub struct Delta {
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// Change in percent of baseline.
+    pub change: f64,
+    pub regressed: bool,
+}
+
+/// Changes of all metrics of one scenario.
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    pub deltas: Vec<Delta>,
+}
+
+impl Comparison {
+    /// Returns true if any metric regresses.
+    #[inline]
+    pub fn regressed(&self) -> bool {
+        self.deltas.iter().any(|d| d.regressed)
+    }
+
+    /// Push baseline value and change of each metric to the record.
+    /// Metric not compared is pushed with empty values, so that records
+    /// have same fields whichever metrics are compared.
+    #[inline]
+    pub fn push_to(&self, record: &mut Record) {
+        for (metric, _) in METRICS {
+            match self.deltas.iter().find(|d| d.metric == metric) {
+                Some(d) => {
+                    record.push(format!("{}_baseline", metric), d.baseline);
+                    record.push(format!("{}_change_pct", metric), d.change);
+                }
+                None => {
+                    record.push(format!("{}_baseline", metric), "");
+                    record.push(format!("{}_change_pct", metric), "");
+                }
+            }
+        }
+        record.push("regressed", self.regressed());
+    }
+
+    /// Push fields of [`Comparison::push_to`] with empty values to record
+    /// of scenario not found in baseline, so that all CSV rows match the
+    /// header.
+    #[inline]
+    pub fn push_empty(record: &mut Record) {
+        for (metric, _) in METRICS {
+            record.push(format!("{}_baseline", metric), "");
+            record.push(format!("{}_change_pct", metric), "");
+        }
+        record.push("regressed", "");
+    }
+}
+
+impl fmt::Display for Comparison {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.deltas.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} is {:.0} -> {:.0} ({:+.1}%)", d.metric, d.baseline, d.current, d.change)?;
+            if d.regressed {
+                f.write_str(" regressed")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(transport: &str, throughput: f64, p50_ns: u64) -> Record {
+        let mut record = Record::new();
+        record.push("transport", transport.to_string());
+        record.push("value", "none");
+        record.push("throughput", throughput);
+        record.push("p50_ns", p50_ns);
+        record
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = Baseline{records: vec![record("unix", 1000.0, 100), record("shm", 2000.0, 50)]};
+        let cmp = baseline.compare(&record("shm", 1500.0, 51), 5.0).unwrap();
+        assert_eq!(cmp.deltas.len(), 2);
+        assert_eq!(cmp.deltas[0].metric, "throughput");
+        assert_eq!(cmp.deltas[0].change, -25.0);
+        assert!(cmp.deltas[0].regressed);
+        assert_eq!(cmp.deltas[1].change, 2.0);
+        assert!(!cmp.deltas[1].regressed);
+        assert!(cmp.regressed());
+        assert!(baseline.compare(&record("tcp", 1000.0, 100), 5.0).is_none());
+    }
+
+    #[test]
+    fn test_compare_missing_field() {
+        let baseline = Baseline{records: vec![record("shm", 2000.0, 50)]};
+        // key field only in current record
+        let mut current = record("shm", 2000.0, 50);
+        current.push("svr_cpu", 0u64);
+        assert!(baseline.compare(&current, 5.0).is_none());
+        // key field only in baseline record
+        let mut base = record("shm", 2000.0, 50);
+        base.push("svr_cpu", 0u64);
+        let baseline = Baseline{records: vec![base]};
+        assert!(baseline.compare(&record("shm", 2000.0, 50), 5.0).is_none());
+        assert!(baseline.compare(&current, 5.0).is_some());
+    }
+
+    #[test]
+    fn test_compare_zero_baseline() {
+        let baseline = Baseline{records: vec![record("shm", 0.0, 0)]};
+        let cmp = baseline.compare(&record("shm", 1000.0, 10), 5.0).unwrap();
+        assert!(cmp.deltas.is_empty());
+        assert!(!cmp.regressed());
+        let mut current = record("shm", 1000.0, 10);
+        cmp.push_to(&mut current);
+        assert!(current.to_json().ends_with(",\"regressed\":false}"));
+    }
+
+    #[test]
+    fn test_push_mixed_scenarios() {
+        let baseline = Baseline{records: vec![record("shm", 2000.0, 50)]};
+        let mut matched = record("shm", 1000.0, 50);
+        baseline.compare(&matched, 5.0).unwrap().push_to(&mut matched);
+        let mut unmatched = record("tcp", 1000.0, 50);
+        assert!(baseline.compare(&unmatched, 5.0).is_none());
+        Comparison::push_empty(&mut unmatched);
+        assert_eq!(matched.csv_header(), unmatched.csv_header());
+        assert!(matched.csv_header().ends_with(",p99_ns_baseline,p99_ns_change_pct,regressed"));
+        assert_eq!(matched.to_csv(), "shm,none,1000,50,2000,-50,50,0,,,true");
+        assert_eq!(unmatched.to_csv(), "tcp,none,1000,50,,,,,,,");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("benchshm-baseline-{}.csv", std::process::id()));
+        let mut repeated = record("unix", 1000.0, 100);
+        repeated.push("throughput_mean", 900.5);
+        let records = vec![record("unix", 1000.0, 100), record("shm", 2000.5, 50), repeated];
+        Baseline::save(&path, &records).unwrap();
+        let baseline = Baseline::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(baseline.records.len(), 3);
+        for (loaded, saved) in baseline.records.iter().zip(&records) {
+            assert_eq!(loaded.to_csv(), saved.to_csv());
+            assert_eq!(loaded.csv_header(), saved.csv_header());
+        }
+        let cmp = baseline.compare(&record("shm", 2000.5, 50), 5.0).unwrap();
+        assert_eq!(cmp.deltas[0].baseline, 2000.5);
+        assert!(!cmp.regressed());
+    }
+}
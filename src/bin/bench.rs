@@ -0,0 +1,461 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use shared_memory::ShmemConf;
+use benchshm::{
+    parse_conn_kind, parse_duration, parse_sizes, Baseline, Comparison, ConnKind, OutputFormat,
+    Printer, Record, Warmup,
+};
+
+/// How long to wait for server to listen.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval to check whether client exited.
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+fn main() -> Result<()> {
+    let args = BenchArgs::parse_from_env()?;
+    let printer = Printer::new(args.format);
+    let baseline = args.baseline.as_ref().map(Baseline::load).transpose()?;
+    let mut records = vec![];
+    let mut regressed = 0;
+    for &kind in &args.transports {
+        let addr = args.addr(kind);
+        for placement in &args.placements {
+            args.format.info(format_args!("starting server at {}, cpu is {}", addr, cpu_str(placement.server)));
+            let server = Server::spawn(&args, &addr, placement.server)?;
+            for workload in &args.workloads {
+                if kind == ConnKind::Shm && matches!(workload, Workload::Sizes(_)) {
+                    // cli rejects payload sweep over shm cabin
+                    args.format.info(format_args!("skipping {} over {}", workload, kind.scheme()));
+                    continue
+                }
+                args.format.info(format_args!("running client of {}, cpu is {}", workload, cpu_str(placement.client)));
+                let rows = match run_client(&args, &addr, placement.client, workload) {
+                    Ok(rows) => rows,
+                    Err(e) => {
+                        eprintln!("client of {} at {} failed: {:#}", workload, addr, e);
+                        continue
+                    }
+                };
+                for mut record in rows {
+                    record.push("svr_cpu", cpu_str(placement.server));
+                    if let Some(baseline) = &baseline {
+                        match baseline.compare(&record, args.threshold) {
+                            Some(cmp) => {
+                                args.format.info(format_args!("compared with baseline: {}", cmp));
+                                regressed += cmp.regressed() as usize;
+                                cmp.push_to(&mut record);
+                            }
+                            None => {
+                                args.format.info(format_args!("scenario not found in baseline"));
+                                Comparison::push_empty(&mut record);
+                            }
+                        }
+                    }
+                    if args.format != OutputFormat::Text {
+                        printer.print(&record);
+                    }
+                    records.push(record);
+                }
+            }
+            drop(server);
+        }
+    }
+    if args.format == OutputFormat::Text {
+        print_table(&records);
+    }
+    if let Some(path) = &args.save_baseline {
+        Baseline::save(path, &records)?;
+        args.format.info(format_args!("saved baseline to {}", path.display()));
+    }
+    if regressed > 0 {
+        bail!("{} of {} scenarios regressed beyond {}%", regressed, records.len(), args.threshold);
+    }
+    Ok(())
+}
+
+fn cpu_str(cpu: Option<usize>) -> String {
+    cpu.map_or_else(|| "none".to_string(), |cpu| cpu.to_string())
+}
+
+/// Server is a child process of svr, killed when dropped, and its
+/// flink or socket file is removed. Shared memory segment linked by
+/// the flink file is unlinked as well, as killed server can not do it.
+struct Server {
+    child: Child,
+    file: Option<PathBuf>,
+    shm: bool,
+}
+
+impl Server {
+    /// Spawn svr and wait until it listens.
+    fn spawn(args: &BenchArgs, addr: &str, cpu: Option<usize>) -> Result<Self> {
+        let (kind, path) = parse_conn_kind(addr)?;
+        let file = (!matches!(kind, ConnKind::Tcp | ConnKind::TcpUring)).then(|| PathBuf::from(path));
+        let shm = matches!(kind, ConnKind::Shm | ConnKind::ShmBytes | ConnKind::ShmRing | ConnKind::ShmStream);
+        // remove file left by killed server
+        if let Some(file) = &file {
+            if shm {
+                unlink_segment(file);
+            }
+            _ = fs::remove_file(file);
+        }
+        let mut cmd = Command::new(args.bin_dir.join("svr"));
+        // one cabin for each client thread of shared memory
+        cmd.arg("-a").arg(addr)
+            .arg("-s").arg(args.spin_only.to_string())
+            .arg("--sqpoll").arg(args.sqpoll.to_string())
+            .arg("-l").arg(args.threads.to_string());
+        // serial socket server serves one client thread at a time
+        if args.threads > 1 && matches!(kind, ConnKind::Tcp | ConnKind::Unix | ConnKind::TcpUring | ConnKind::UnixUring) {
+            cmd.arg("--mode").arg("thread");
+        }
+        if let Some(cpu) = cpu {
+            cmd.arg("-c").arg(cpu.to_string());
+        }
+        let mut child = cmd.stdout(Stdio::piped()).spawn().context("fail to spawn svr")?;
+        let stdout = child.stdout.take().unwrap();
+        let server = Server{child, file, shm};
+        // keep draining output so that server never blocks on it
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+                if line.starts_with("Listening at") {
+                    _ = tx.send(());
+                }
+            }
+        });
+        match rx.recv_timeout(READY_TIMEOUT) {
+            Ok(()) => Ok(server),
+            Err(mpsc::RecvTimeoutError::Timeout) => bail!("svr is not ready in {:?}", READY_TIMEOUT),
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("svr exited before ready"),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+        if let Some(file) = &self.file {
+            if self.shm {
+                unlink_segment(file);
+            }
+            _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// Unlink shared memory segment linked by the flink file.
+fn unlink_segment(flink: &Path) {
+    if let Ok(mut shmem) = ShmemConf::new().flink(flink).open() {
+        // owner unlinks the segment when dropped
+        shmem.set_owner(true);
+    }
+}
+
+/// Read all output of the pipe on another thread, so that child never
+/// blocks on it.
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        _ = pipe.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+/// Wait for child to exit, returns None if timed out.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let inst = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status))
+        }
+        if inst.elapsed() >= timeout {
+            return Ok(None)
+        }
+        thread::sleep(EXIT_CHECK_INTERVAL);
+    }
+}
+
+/// Run cli with CSV output, returns one record for each scenario.
+/// Client is killed if it does not finish in time.
+fn run_client(args: &BenchArgs, addr: &str, cpu: Option<usize>, workload: &Workload) -> Result<Vec<Record>> {
+    let mut cmd = Command::new(args.bin_dir.join("cli"));
+    cmd.arg("-a").arg(addr)
+        .arg("-n").arg(args.num.to_string())
+        .arg("-s").arg(args.spin_only.to_string())
+        .arg("--sqpoll").arg(args.sqpoll.to_string())
+        .arg("-W").arg(&args.warmup)
+        .arg("-r").arg(args.repeat.to_string())
+        .arg("-t").arg(args.threads.to_string())
+        .arg("-f").arg("csv");
+    if let Some(cpu) = cpu {
+        cmd.arg("-c").arg(cpu.to_string());
+    }
+    match workload {
+        Workload::Value(None) => (),
+        Workload::Value(Some(value)) => { cmd.arg("-v").arg(value.to_string()); }
+        Workload::Window(window) => { cmd.arg("--window").arg(window.to_string()); }
+        Workload::Rate(rate, arrival) => { cmd.arg("--rate").arg(rate.to_string()).arg("--arrival").arg(arrival); }
+        Workload::Sizes(sizes) => { cmd.arg("--sizes").arg(sizes); }
+    }
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().context("fail to run cli")?;
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+    let Some(status) = wait_timeout(&mut child, args.timeout)? else {
+        _ = child.kill();
+        _ = child.wait();
+        bail!("cli is not finished in {:?}", args.timeout);
+    };
+    if !status.success() {
+        bail!("{}, {}", status, stderr.join().unwrap().trim());
+    }
+    let stdout = stdout.join().unwrap();
+    let mut lines = stdout.lines();
+    let header = lines.next().context("no output of cli")?;
+    Ok(lines.map(|row| Record::from_csv(header, row)).collect())
+}
+
+/// Print one row for each record with main metrics, summarized
+/// metrics are used if runs are repeated.
+fn print_table(records: &[Record]) {
+    const COLUMNS: [&str; 10] = ["transport", "threads", "mode", "size", "svr_cpu", "cli_cpu", "ops/s", "MiB/s", "p50", "p99"];
+    let metric = |record: &Record, name: &str| record.metric(name).unwrap_or(f64::NAN);
+    let text = |record: &Record, name: &str| record.get(name).map(|f| f.to_string()).unwrap_or_default();
+    // value of window and rate mode is always odd sequence
+    let mode = |record: &Record| match (text(record, "rate").as_str(), text(record, "window").as_str()) {
+        ("" | "none", "" | "0") => text(record, "value"),
+        ("" | "none", window) => format!("window {}", window),
+        (rate, _) => rate.to_string(),
+    };
+    let rows: Vec<[String; 10]> = records.iter()
+        .map(|r| [
+            text(r, "transport"),
+            text(r, "threads"),
+            mode(r),
+            text(r, "size"),
+            text(r, "svr_cpu"),
+            text(r, "cpu"),
+            format!("{:.0}", metric(r, "throughput")),
+            format!("{:.1}", metric(r, "bandwidth") / (1 << 20) as f64),
+            format!("{:?}", Duration::from_nanos(metric(r, "p50_ns") as u64)),
+            format!("{:?}", Duration::from_nanos(metric(r, "p99_ns") as u64)),
+        ])
+        .collect();
+    let mut widths = COLUMNS.map(str::len);
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    let print_row = |cells: &[String]| {
+        let cells: Vec<_> = cells.iter().zip(widths).map(|(cell, w)| format!("{:>w$}", cell, w = w)).collect();
+        println!("{}", cells.join("  "));
+    };
+    print_row(&COLUMNS.map(String::from));
+    for row in &rows {
+        print_row(row);
+    }
+}
+
+/// Workload of client in the matrix.
+#[derive(Debug)]
+enum Workload {
+    /// Fixed value, or sequence number if not specified.
+    Value(Option<u64>),
+    Window(usize),
+    /// Target rate and arrival process.
+    Rate(f64, &'static str),
+    /// Payload sizes swept by one client.
+    Sizes(String),
+}
+
+impl fmt::Display for Workload {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Workload::Value(None) => f.write_str("seq"),
+            Workload::Value(Some(value)) => write!(f, "value {}", value),
+            Workload::Window(window) => write!(f, "window {}", window),
+            Workload::Rate(rate, arrival) => write!(f, "{} rate {}", arrival, rate),
+            Workload::Sizes(sizes) => write!(f, "sizes {}", sizes),
+        }
+    }
+}
+
+/// CPUs of server and client, not pinned if none.
+#[derive(Debug)]
+struct Placement {
+    server: Option<usize>,
+    client: Option<usize>,
+}
+
+/// Parse placement like "0:1", either side can be "none".
+fn parse_placement(s: &str) -> Result<Placement> {
+    let parse = |s: &str| -> Result<Option<usize>> {
+        match s {
+            "none" => Ok(None),
+            _ => Ok(Some(s.parse()?)),
+        }
+    };
+    match s.split_once(':') {
+        Some((server, client)) => Ok(Placement{server: parse(server)?, client: parse(client)?}),
+        None if s == "none" => Ok(Placement{server: None, client: None}),
+        None => bail!("invalid placement {:?}, expected SVR_CPU:CLI_CPU", s),
+    }
+}
+
+/// Parse value mode of "seq", a number, "window:N", "rate:N" or
+/// "poisson:N".
+fn parse_workload(s: &str) -> Result<Workload> {
+    if s == "seq" {
+        return Ok(Workload::Value(None))
+    }
+    match s.split_once(':') {
+        Some(("window", window)) => Ok(Workload::Window(window.parse()?)),
+        Some(("rate", rate)) => Ok(Workload::Rate(rate.parse()?, "constant")),
+        Some(("poisson", rate)) => Ok(Workload::Rate(rate.parse()?, "poisson")),
+        Some(_) => bail!("unknown value mode {:?}", s),
+        None => Ok(Workload::Value(Some(s.parse()?))),
+    }
+}
+
+#[derive(Debug)]
+struct BenchArgs {
+    transports: Vec<ConnKind>,
+    workloads: Vec<Workload>,
+    placements: Vec<Placement>,
+    num: u32,
+    spin_only: bool,
+    /// Use SQPOLL for io_uring transports.
+    sqpoll: bool,
+    warmup: String,
+    repeat: usize,
+    threads: usize,
+    format: OutputFormat,
+    /// Directory of flink and socket files.
+    dir: PathBuf,
+    port: u16,
+    /// Directory of svr and cli binaries.
+    bin_dir: PathBuf,
+    baseline: Option<PathBuf>,
+    save_baseline: Option<PathBuf>,
+    /// Percent of change regarded as regression.
+    threshold: f64,
+    /// How long each client may run before killed.
+    timeout: Duration,
+}
+
+impl BenchArgs {
+    /// Returns address of the transport.
+    #[inline]
+    fn addr(&self, kind: ConnKind) -> String {
+        match kind {
+            ConnKind::Tcp | ConnKind::TcpUring => format!("{}:127.0.0.1:{}", kind.scheme(), self.port),
+            _ => format!("{}:{}", kind.scheme(), self.dir.join(format!("benchshm-{}.tmp", kind.scheme())).display()),
+        }
+    }
+
+    #[inline]
+    fn parse_from_env() -> Result<BenchArgs> {
+        use lexopt::prelude::*;
+        let mut parser = lexopt::Parser::from_env();
+        let mut transports = vec![ConnKind::Tcp, ConnKind::Unix, ConnKind::Shm];
+        let mut workloads = vec![Workload::Value(None)];
+        let mut sizes = None;
+        let mut placements = vec![Placement{server: None, client: None}];
+        let mut num = 1024;
+        let mut spin_only = false;
+        let mut sqpoll = false;
+        let mut warmup = "0".to_string();
+        let mut repeat = 1usize;
+        let mut threads = 1usize;
+        let mut format = OutputFormat::Text;
+        let mut dir = env::temp_dir();
+        let mut port = 9001;
+        let mut bin_dir = env::current_exe()?.parent().map(PathBuf::from).unwrap_or_default();
+        let mut baseline = None;
+        let mut save_baseline = None;
+        let mut threshold = 5.0;
+        let mut timeout = Duration::from_secs(300);
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Short('t') | Long("transports") => {
+                    transports = parser.value()?.to_string_lossy().split(',')
+                        .map(|s| ConnKind::ALL.into_iter().find(|kind| kind.scheme() == s)
+                            .with_context(|| format!("unknown transport {:?}", s)))
+                        .collect::<Result<_>>()?
+                }
+                Short('v') | Long("values") => {
+                    workloads = parser.value()?.to_string_lossy().split(',').map(parse_workload).collect::<Result<_>>()?
+                }
+                Long("sizes") => {
+                    let s = parser.value()?.to_string_lossy().to_string();
+                    parse_sizes(&s)?;
+                    sizes = Some(s)
+                }
+                Short('c') | Long("cpus") => {
+                    placements = parser.value()?.to_string_lossy().split(',').map(parse_placement).collect::<Result<_>>()?
+                }
+                Short('n') | Long("num") => {
+                    num = parser.value()?.parse()?
+                }
+                Short('s') | Long("spin-only") => {
+                    spin_only = parser.value()?.parse()?
+                }
+                Long("sqpoll") => {
+                    sqpoll = parser.value()?.parse()?
+                }
+                Short('W') | Long("warmup") => {
+                    let s = parser.value()?.to_string_lossy().to_string();
+                    s.parse::<Warmup>()?;
+                    warmup = s
+                }
+                Short('r') | Long("repeat") => {
+                    repeat = parser.value()?.parse()?
+                }
+                Long("threads") => {
+                    threads = parser.value()?.parse()?
+                }
+                Short('f') | Long("format") => {
+                    format = parser.value()?.parse()?
+                }
+                Short('d') | Long("dir") => {
+                    dir = parser.value()?.into()
+                }
+                Short('p') | Long("port") => {
+                    port = parser.value()?.parse()?
+                }
+                Long("bin-dir") => {
+                    bin_dir = parser.value()?.into()
+                }
+                Short('b') | Long("baseline") => {
+                    baseline = Some(parser.value()?.into())
+                }
+                Long("save-baseline") => {
+                    save_baseline = Some(parser.value()?.into())
+                }
+                Long("threshold") => {
+                    threshold = parser.value()?.parse()?
+                }
+                Long("timeout") => {
+                    timeout = parse_duration(&parser.value()?.to_string_lossy())?
+                }
+                _ => return Err(arg.unexpected().into())
+            }
+        }
+        if let Some(sizes) = sizes {
+            workloads.push(Workload::Sizes(sizes));
+        }
+        Ok(BenchArgs{transports, workloads, placements, num, spin_only, sqpoll, warmup, repeat: repeat.max(1), threads: threads.max(1), format, dir, port, bin_dir, baseline, save_baseline, threshold, timeout})
+    }
+}
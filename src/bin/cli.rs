@@ -1,135 +1,298 @@
 use anyhow::Result;
-use std::net::TcpStream;
-use std::os::unix::net::UnixStream;
-use std::time::Instant;
-use benchshm::{client_conn, parse_conn_kind, ConnKind, Cabin, CabinState, CabinGuard};
-use shared_memory::ShmemConf;
-use crossbeam_utils::Backoff;
-use std::sync::atomic::Ordering;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use benchshm::{
+    client_workload, online_cpus, parse_conn_kind, parse_cpu_list, parse_sizes, pin_cpu,
+    warmup_conn, Arrival, Baseline, Comparison, ConnKind, Connection, Error, HostInfo, Latency,
+    OutputFormat, Printer, Rate, Record, ShmBytesTransport, ShmRingTransport, ShmStreamTransport,
+    ShmTransport, Summary, TcpTransport, TcpUringTransport, Transport, TransportOpts,
+    UnixTransport, UnixUringTransport, Warmup, Workload, PAYLOAD_FLAG,
+};
 
 fn main() -> anyhow::Result<()> {
     let args = CliArgs::parse_from_env()?;
 
-    println!("connecting ({:?})({})", args.addr.0, args.addr.1);
+    args.format.info(format_args!("connecting ({:?})({})", args.addr.0, args.addr.1));
 
-    let (sum, dur, yield_num) = match args.addr.0 {
-        ConnKind::Tcp => {
-            let conn = TcpStream::connect(&args.addr.1)?;
-            let inst = Instant::now();
-            let sum = client_conn(conn, args.value, args.num)?;
-            let dur = inst.elapsed();
-            (sum, dur, 0)
+    let printer = Printer::new(args.format);
+    let host = HostInfo::collect();
+    // each thread is pinned to next cpu of the list when it starts
+    let online = online_cpus()?;
+    if let Some(&cpu) = args.cpus.iter().find(|cpu| !online.contains(cpu)) {
+        return Err(Error::CpuNotOnline(cpu).into())
+    }
+    let baseline = args.baseline.as_ref().map(Baseline::load).transpose()?;
+    let sizes = match &args.sizes {
+        Some(sizes) => sizes.iter().map(|&size| Some(size)).collect(),
+        None => vec![None],
+    };
+    let mut records = vec![];
+    let mut regressed = 0;
+    for size in sizes {
+        let mut record = scenario(&args, size, &host)?;
+        if let Some(baseline) = &baseline {
+            match baseline.compare(&record, args.threshold) {
+                Some(cmp) => {
+                    args.format.info(format_args!("compared with baseline: {}", cmp));
+                    regressed += cmp.regressed() as usize;
+                    cmp.push_to(&mut record);
+                }
+                None => {
+                    args.format.info(format_args!("scenario not found in baseline"));
+                    Comparison::push_empty(&mut record);
+                }
+            }
         }
-        ConnKind::Unix => {
-            let conn = UnixStream::connect(&args.addr.1)?;
-            let inst = Instant::now();
-            let sum = client_conn(conn, args.value, args.num)?;
-            let dur = inst.elapsed();
-            (sum, dur, 0)
+        if args.format != OutputFormat::Text {
+            printer.print(&record);
         }
-        ConnKind::Shm => {
-            unsafe {
-                let shmem = ShmemConf::new().size(4096).flink(&args.addr.1).open()?;
-                let raw_ptr = shmem.as_ptr();
-                let guard = CabinGuard;
-                let cabin: &Cabin<u64, u64> = Cabin::from_existing(raw_ptr, &guard);
-                let client_id: u32 = rand::random();
-                let mut sum = 0;
-                let mut value = 0;
-                let mut id_written = false;
-                let mut req_written = false;
-                let mut yield_num = 0usize;
-                let mut inst = Instant::now();
-                loop {
-                    match cabin.load_state(Ordering::Acquire) {
-                        CabinState::AcceptingSpin => {
-                            cabin.set_id(client_id);
-                            id_written = true;
-                            inst = Instant::now();
-                            _ = cabin.cas_state(CabinState::AcceptingSpin, CabinState::Connecting);
-                        }
-                        CabinState::AcceptingYield => {
-                            if !id_written {
-                                cabin.set_id(client_id);
-                                inst = Instant::now();
-                                id_written = true;
-                            }
-                            let lg = cabin.lock().unwrap();
-                            assert!(cabin.cas_state(CabinState::AcceptingYield, CabinState::Connecting).is_ok());
-                            lg.signal().unwrap();
-                        }
-                        CabinState::Connecting => {
-                            let backoff = Backoff::new();
-                            backoff.snooze();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::Connecting {
-                                backoff.snooze(); // todo: yield if spin limit reached
-                            }
-                        }
-                        CabinState::WaitReqSpin => {
-                            let resp = cabin.resp();
-                            debug_assert_eq!(sum, resp);
-                            if value >= args.num as u64 {
-                                _ = cabin.cas_state(CabinState::WaitReqSpin, CabinState::Disconnected);
-                            } else {
-                                cabin.set_req(value);
-                                sum += value;
-                                value += 1;
-                                req_written = true;
-                                if cabin.cas_state(CabinState::WaitReqSpin, CabinState::WaitRespSpin).is_ok() {
-                                    req_written = false;
-                                }
-                            }
-                        }
-                        CabinState::WaitReqYield => {
-                            let resp = cabin.resp();
-                            debug_assert_eq!(sum, resp);
-                            if value >= args.num as u64 {
-                                _ = cabin.cas_state(CabinState::WaitReqYield, CabinState::Disconnected);
-                                break
-                            } else {
-                                if !req_written {
-                                    cabin.set_req(value);
-                                    sum += value;
-                                    value += 1;
-                                }
-                                let lg = cabin.lock().unwrap();
-                                assert!(cabin.cas_state(CabinState::WaitReqYield, CabinState::WaitRespSpin).is_ok());
-                                req_written = false;
-                                lg.signal().unwrap();
-                            }
-                        }
-                        CabinState::WaitRespSpin => {
-                            let backoff = Backoff::new();
-                            backoff.snooze();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::WaitRespSpin {
-                                if !args.spin_only && backoff.is_completed() {
-                                    // try yield current thread
-                                    _ = cabin.cas_state(CabinState::WaitRespSpin, CabinState::WaitRespYield);
-                                    break
-                                } else {
-                                    backoff.snooze();
-                                }
-                            }
-                        }
-                        CabinState::WaitRespYield => {
-                            yield_num += 1;
-                            // blocking wait
-                            let lg = cabin.lock().unwrap();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::WaitRespYield {
-                                lg.wait()?;
-                            }
-                        }
-                        CabinState::Disconnected => break,
-                    }
-                }
-                let dur = inst.elapsed();
-                (sum, dur, yield_num)
+        records.push(record);
+    }
+    if let Some(path) = &args.save_baseline {
+        Baseline::save(path, &records)?;
+        args.format.info(format_args!("saved baseline to {}", path.display()));
+    }
+    if regressed > 0 {
+        anyhow::bail!("{} of {} scenarios regressed beyond {}%", regressed, records.len(), args.threshold);
+    }
+    Ok(())
+}
+
+/// Run the scenario repeatedly, with payload of given size if specified,
+/// and returns record of the result.
+fn scenario(args: &CliArgs, size: Option<usize>, host: &HostInfo) -> Result<Record> {
+    if let Some(size) = size {
+        args.format.info(format_args!("payload size is {}", size));
+    }
+    // reconnect for each run
+    let mut runs = Vec::with_capacity(args.repeat);
+    for _ in 0..args.repeat {
+        let run = match args.addr.0 {
+            ConnKind::Tcp => run::<TcpTransport>(args, size)?,
+            ConnKind::Unix => run::<UnixTransport>(args, size)?,
+            ConnKind::TcpUring => run::<TcpUringTransport>(args, size)?,
+            ConnKind::UnixUring => run::<UnixUringTransport>(args, size)?,
+            ConnKind::Shm => run::<ShmTransport>(args, size)?,
+            ConnKind::ShmBytes => run::<ShmBytesTransport>(args, size)?,
+            ConnKind::ShmRing => run::<ShmRingTransport>(args, size)?,
+            ConnKind::ShmStream => run::<ShmStreamTransport>(args, size)?,
+        };
+        args.format.info(format_args!("disconnected: num is {}, sum is {}, duration is {:?}, avg latency is {:?}, yields is {}", run.count, run.sum, run.dur, run.dur / args.num, run.yields));
+        args.format.info(format_args!("throughput: {:.0} ops/s, bandwidth: {:.1} MiB/s", run.throughput(), run.bandwidth() / (1 << 20) as f64));
+        args.format.info(format_args!("latency: {}", run.latency.report()));
+        runs.push(run);
+    }
+
+    let summary = |f: &dyn Fn(&RunResult) -> f64| Summary::of(&runs.iter().map(f).collect::<Vec<_>>());
+    let throughput = summary(&|run| run.throughput());
+    let bandwidth = summary(&|run| run.bandwidth());
+    let p50 = summary(&|run| run.latency.report().p50.as_nanos() as f64);
+    let p99 = summary(&|run| run.latency.report().p99.as_nanos() as f64);
+    if args.format == OutputFormat::Text && args.repeat > 1 {
+        println!("summary of {} runs:", args.repeat);
+        println!("throughput (ops/s): {}", throughput);
+        println!("bandwidth (bytes/s): {}", bandwidth);
+        println!("p50 latency (ns): {}", p50);
+        println!("p99 latency (ns): {}", p99);
+    }
+    let mut record = Record::new();
+    record.push("transport", args.addr.0.scheme());
+    record.push("addr", args.addr.1.as_str());
+    record.push("num", args.num);
+    record.push("threads", args.threads);
+    let value = match (args.value, size) {
+        (_, Some(_)) => "payload".to_string(),
+        (Some(v), None) => v.to_string(),
+        // window and rate mode send odd value 2*seq+1
+        (None, None) if args.window.is_some() || args.rate.is_some() => "odd-seq".to_string(),
+        (None, None) => "seq".to_string(),
+    };
+    record.push("value", value);
+    record.push("size", size.unwrap_or(8));
+    record.push("spin_only", args.spin_only);
+    record.push("sqpoll", args.sqpoll);
+    // string so that unpinned run is "none"
+    let cpus: Vec<_> = args.cpus.iter().map(|cpu| cpu.to_string()).collect();
+    record.push("cpu", if cpus.is_empty() { "none".to_string() } else { cpus.join(",") });
+    record.push("window", runs[0].window);
+    record.push("rate", args.rate.map_or_else(|| "none".to_string(), |rate| rate.to_string()));
+    record.push("warmup", args.warmup.to_string());
+    if let [run] = &runs[..] {
+        record.push("sum", run.sum);
+        record.push("duration_ns", run.dur);
+        record.push("yields", run.yields);
+        record.push("throughput", run.throughput());
+        record.push("bandwidth", run.bandwidth());
+        record.push_latency(&run.latency.report());
+    } else {
+        record.push("repeat", args.repeat);
+        record.push_summary("throughput", &throughput);
+        record.push_summary("bandwidth", &bandwidth);
+        record.push_summary("p50_ns", &p50);
+        record.push_summary("p99_ns", &p99);
+    }
+    record.push_host(host);
+    Ok(record)
+}
+
+/// Result of one run, merged from all threads.
+struct RunResult {
+    /// Number of requests measured.
+    count: u64,
+    sum: u64,
+    dur: Duration,
+    yields: usize,
+    /// Effective window, 0 if not in window mode.
+    window: usize,
+    /// Bytes of each request.
+    size: usize,
+    latency: Latency,
+}
+
+impl RunResult {
+    /// Returns requests per second.
+    #[inline]
+    fn throughput(&self) -> f64 {
+        self.count as f64 / self.dur.as_secs_f64()
+    }
+
+    /// Returns bytes of requests per second.
+    #[inline]
+    fn bandwidth(&self) -> f64 {
+        self.throughput() * self.size as f64
+    }
+
+    /// Merge result of another thread started at the same time,
+    /// so duration is the longest one.
+    #[inline]
+    fn merge(mut self, other: RunResult) -> Self {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.dur = self.dur.max(other.dur);
+        self.yields += other.yields;
+        self.latency.merge(&other.latency);
+        self
+    }
+}
+
+/// How long each thread waits for other threads to connect and warm up.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Barrier to start measurement of all threads. It is broken if any
+/// thread fails to prepare, or not all threads arrive in time, e.g. when
+/// server serves one connection at a time.
+struct StartBarrier {
+    threads: usize,
+    /// Number of arrived threads, and whether the barrier is broken.
+    state: Mutex<(usize, bool)>,
+    cond: Condvar,
+}
+
+impl StartBarrier {
+    fn new(threads: usize) -> Self {
+        StartBarrier{threads, state: Mutex::new((0, false)), cond: Condvar::new()}
+    }
+
+    /// Wait for all threads, returns Ok(false) if broken by another
+    /// thread, or error if timed out.
+    fn wait(&self, ready: bool) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        state.1 |= !ready;
+        if state.0 < self.threads && !state.1 {
+            let (mut s, res) = self.cond.wait_timeout_while(state, START_TIMEOUT, |(arrived, broken)| *arrived < self.threads && !*broken).unwrap();
+            if res.timed_out() {
+                s.1 = true;
+                self.cond.notify_all();
+                anyhow::bail!("not all threads are connected and warmed up in {:?}, svr may serve one connection at a time, try svr --mode thread", START_TIMEOUT);
             }
+            state = s;
         }
+        self.cond.notify_all();
+        Ok(!state.1)
+    }
+}
+
+/// Run all threads, each with its own connection.
+fn run<T: Transport>(args: &CliArgs, size: Option<usize>) -> Result<RunResult> {
+    let barrier = StartBarrier::new(args.threads);
+    let results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..args.threads)
+            .map(|i| {
+                let barrier = &barrier;
+                s.spawn(move || run_conn::<T>(args, size, i, barrier))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut merged: Option<RunResult> = None;
+    let mut aborted = false;
+    // error of failed thread is returned instead of aborted ones
+    for res in results {
+        let Some(run) = res? else {
+            aborted = true;
+            continue
+        };
+        merged = Some(match merged {
+            Some(merged) => merged.merge(run),
+            None => run,
+        });
+    }
+    match merged {
+        Some(merged) if !aborted => Ok(merged),
+        _ => anyhow::bail!("run is aborted as another thread failed"),
+    }
+}
+
+/// Run one connection, measurement starts when all threads are connected
+/// and warmed up. Returns None if aborted as another thread failed.
+fn run_conn<T: Transport>(args: &CliArgs, size: Option<usize>, idx: usize, barrier: &StartBarrier) -> Result<Option<RunResult>> {
+    let prepare = || -> Result<_> {
+        // threads are pinned to cpus of the list in round robin
+        if !args.cpus.is_empty() {
+            let cpu = args.cpus[idx % args.cpus.len()];
+            pin_cpu(cpu)?;
+            args.format.info(format_args!("thread {} pinned to cpu {}", idx, cpu));
+        }
+        let opts = TransportOpts{spin_only: args.spin_only, sqpoll: args.sqpoll, ..Default::default()};
+        let (mut conn, local) = T::connect(&args.addr.1, &opts)?;
+        args.format.info(format_args!("connected as {}", local));
+        // print options once
+        let info = |msg: std::fmt::Arguments<'_>| if idx == 0 { args.format.info(msg) };
+        if args.warmup != Warmup::None {
+            info(format_args!("warming up with {}", args.warmup));
+        }
+        let (workload, window) = match (args.window, args.rate, size) {
+            (_, _, Some(size)) => (Workload::Payload(size), 0),
+            (_, Some(rate), None) => {
+                info(format_args!("rate is {}", rate));
+                (Workload::Rate(rate), 0)
+            }
+            (Some(window), None, None) => {
+                let window = window.clamp(1, conn.max_in_flight());
+                info(format_args!("window is {}", window));
+                (Workload::Window(window), window)
+            }
+            (None, None, None) => (Workload::Value(args.value), 0),
+        };
+        // warmup is excluded from timing, but included in sum to match server
+        let sum = warmup_conn(&mut conn, workload, args.warmup)?;
+        Ok((conn, workload, window, sum))
     };
-    
-    println!("disconnected: num is {}, sum is {}, duration is {:?}, avg latency is {:?}, yields is {}", args.num, sum, dur, dur / args.num, yield_num);
-    Ok(())
+    let prepared = prepare();
+    // wait even if failed, so that other threads never block
+    if !barrier.wait(prepared.is_ok())? {
+        return prepared.map(|_| None)
+    }
+    let (mut conn, workload, window, mut sum) = prepared?;
+    let mut latency = Latency::new();
+    let inst = Instant::now();
+    sum += client_workload(&mut conn, workload, args.num, &mut latency)?;
+    conn.close()?;
+    let dur = inst.elapsed();
+    Ok(Some(RunResult{count: args.num as u64, sum, dur, yields: conn.yields(), window, size: size.unwrap_or(8), latency}))
 }
 
 #[derive(Debug)]
@@ -137,7 +300,23 @@ pub struct CliArgs {
     pub addr: (ConnKind, String),
     pub num: u32,
     pub value: Option<u64>,
-    pub spin_only: bool
+    pub spin_only: bool,
+    /// Use SQPOLL for io_uring transports.
+    pub sqpoll: bool,
+    pub format: OutputFormat,
+    pub warmup: Warmup,
+    pub repeat: usize,
+    pub window: Option<usize>,
+    pub sizes: Option<Vec<usize>>,
+    /// Threads are pinned to these cpus in round robin.
+    pub cpus: Vec<usize>,
+    pub rate: Option<Rate>,
+    /// Number of threads, each with its own connection.
+    pub threads: usize,
+    pub baseline: Option<PathBuf>,
+    pub save_baseline: Option<PathBuf>,
+    /// Percent of change regarded as regression.
+    pub threshold: f64,
 }
 
 impl CliArgs {
@@ -149,6 +328,19 @@ impl CliArgs {
         let mut num = 1024; // by default 1024
         let mut value = None;
         let mut spin_only = false;
+        let mut sqpoll = false;
+        let mut format = OutputFormat::Text;
+        let mut warmup = Warmup::None;
+        let mut repeat = 1;
+        let mut window = None;
+        let mut sizes = None;
+        let mut cpus = vec![];
+        let mut rate = None;
+        let mut arrival = Arrival::Constant;
+        let mut threads = 1;
+        let mut baseline = None;
+        let mut save_baseline = None;
+        let mut threshold = 5.0;
         while let Some(arg) = parser.next()? {
             match arg {
                 Short('a') | Long("addr") => {
@@ -163,9 +355,61 @@ impl CliArgs {
                 Short('s') | Long("spin-only") => {
                     spin_only = parser.value()?.parse()?
                 }
+                Long("sqpoll") => {
+                    sqpoll = parser.value()?.parse()?
+                }
+                Short('f') | Long("format") => {
+                    format = parser.value()?.parse()?
+                }
+                Short('W') | Long("warmup") => {
+                    warmup = parser.value()?.parse()?
+                }
+                Short('r') | Long("repeat") => {
+                    repeat = parser.value()?.parse()?
+                }
+                Long("window") => {
+                    window = Some(parser.value()?.parse()?)
+                }
+                Long("sizes") => {
+                    sizes = Some(parse_sizes(&parser.value()?.to_string_lossy())?)
+                }
+                Short('c') | Long("cpu") => {
+                    cpus = parse_cpu_list(&parser.value()?.to_string_lossy())?
+                }
+                Short('R') | Long("rate") => {
+                    rate = Some(parser.value()?.parse::<f64>()?)
+                }
+                Long("arrival") => {
+                    arrival = parser.value()?.parse()?
+                }
+                Short('t') | Long("threads") => {
+                    threads = parser.value()?.parse()?
+                }
+                Short('b') | Long("baseline") => {
+                    baseline = Some(parser.value()?.into())
+                }
+                Long("save-baseline") => {
+                    save_baseline = Some(parser.value()?.into())
+                }
+                Long("threshold") => {
+                    threshold = parser.value()?.parse()?
+                }
                 _ => return Err(arg.unexpected().into())
             }
         }
-        Ok(CliArgs{addr: addr.unwrap_or_else(|| parse_conn_kind("tcp:127.0.0.1:9001").unwrap()), num, value, spin_only})
+        if [value.is_some(), window.is_some(), sizes.is_some(), rate.is_some()].into_iter().filter(|b| *b).count() > 1 {
+            anyhow::bail!("value, window, sizes and rate can not be specified together");
+        }
+        if sizes.is_some() && addr.as_ref().is_some_and(|(kind, _)| *kind == ConnKind::Shm) {
+            anyhow::bail!("shm cabin moves payload 8 bytes per round trip, use shm-bytes, shm-ring or shm-stream with sizes");
+        }
+        if rate.is_some_and(|r| !(r > 0.0 && r.is_finite())) {
+            anyhow::bail!("rate must be positive");
+        }
+        let rate = rate.map(|per_sec| Rate{per_sec, arrival});
+        if value.is_some_and(|v| v & PAYLOAD_FLAG != 0) {
+            anyhow::bail!("value must be less than 2^63");
+        }
+        Ok(CliArgs{addr: addr.unwrap_or_else(|| parse_conn_kind("tcp:127.0.0.1:9001").unwrap()), num, value, spin_only, sqpoll, format, warmup, repeat: repeat.max(1), window, sizes, cpus, rate, threads: threads.max(1), baseline, save_baseline, threshold})
     }
 }
@@ -1,141 +1,270 @@
 use anyhow::Result;
-use std::{net::TcpListener, time::Instant};
-use std::os::unix::net::UnixListener;
-use benchshm::{ConnKind, server_conn, parse_conn_kind, Cabin, CabinGuard, CabinState};
-use shared_memory::ShmemConf;
-use crossbeam_utils::Backoff;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{thread, time::{Duration, Instant}};
+use benchshm::{
+    format_page_nodes, online_cpus, parse_conn_kind, parse_cpu_list, pin_cpu, serve_epoll,
+    server_conn, ConnKind, Connection, EpollListener, Error, HostInfo, Listener, OutputFormat,
+    Printer, Record, ServeMode, ShmBytesTransport, ShmRingTransport, ShmStreamTransport,
+    ShmTransport, TcpTransport, TcpUringTransport, Transport, TransportOpts, UnixTransport,
+    UnixUringTransport, WaitBackend,
+};
 
 fn main() -> Result<()> {
     let args = SvrArgs::parse_from_env()?;
-    
-    println!("Listening at ({:?})({})", args.addr.0, args.addr.1);
 
+    let opts = TransportOpts{spin_only: args.spin_only, slots: args.slots, wait: args.wait, node: args.node, sqpoll: args.sqpoll};
+    // shared memory is prefaulted after pinning to first cpu, and each
+    // serving thread is pinned to next cpu of the list
+    let online = online_cpus()?;
+    if let Some(&cpu) = args.cpus.iter().find(|cpu| !online.contains(cpu)) {
+        return Err(Error::CpuNotOnline(cpu).into())
+    }
+    if let Some(&cpu) = args.cpus.first() {
+        pin_cpu(cpu)?;
+        args.format.info(format_args!("pinned to cpu {}", cpu));
+    }
+    // shared memory already has one thread for each slot
+    let socket = matches!(args.addr.0, ConnKind::Tcp | ConnKind::Unix | ConnKind::TcpUring | ConnKind::UnixUring);
+    if args.mode != ServeMode::Serial && !socket {
+        anyhow::bail!("serve mode {} only applies to sockets", args.mode);
+    }
+    // io_uring connections have their own rings
+    if matches!(args.mode, ServeMode::Epoll{..}) && !matches!(args.addr.0, ConnKind::Tcp | ConnKind::Unix) {
+        anyhow::bail!("serve mode {} only applies to tcp and unix", args.mode);
+    }
     match args.addr.0 {
-        ConnKind::Tcp => {
-            let listener = TcpListener::bind(&args.addr.1)?;
-            while let Ok((conn, remote_addr)) = listener.accept() {
-                // use current thread to handle connection
-                let inst = Instant::now();
-                let sum = server_conn(conn)?;
-                let dur = inst.elapsed();
-                println!("disconnected from remote addr {:?}, sum is {}, duration is {:?}", remote_addr, sum, dur);
-            }
-        }
-        ConnKind::Unix => {
-            let listener = UnixListener::bind(&args.addr.1)?;
-            while let Ok((conn, remote_addr)) = listener.accept() {
-                // use current thread to handle connection
-                let inst = Instant::now();
-                let sum = server_conn(conn)?;
-                let dur = inst.elapsed();
-                println!("disconnected from remote addr {:?}, sum is {}, duration is {:?}", remote_addr, sum, dur);
-            }
+        ConnKind::Tcp => serve::<TcpTransport>(args, &opts, serve_socket),
+        ConnKind::Unix => serve::<UnixTransport>(args, &opts, serve_socket),
+        ConnKind::TcpUring => serve::<TcpUringTransport>(args, &opts, serve_threads),
+        ConnKind::UnixUring => serve::<UnixUringTransport>(args, &opts, serve_threads),
+        ConnKind::Shm => serve::<ShmTransport>(args, &opts, serve_listener),
+        ConnKind::ShmBytes => serve::<ShmBytesTransport>(args, &opts, serve_listener),
+        ConnKind::ShmRing => serve::<ShmRingTransport>(args, &opts, serve_listener),
+        ConnKind::ShmStream => serve::<ShmStreamTransport>(args, &opts, serve_listener),
+    }
+}
+
+type ServeFn<L> = fn(L, &Arc<Reporter>) -> Result<()>;
+
+fn serve<T: Transport>(args: SvrArgs, opts: &TransportOpts, serve_fn: ServeFn<T::Listener>) -> Result<()> {
+    let listeners = T::listen(&args.addr.1, opts)?;
+    args.format.info(format_args!("Listening at ({:?})({}), wait backend is {:?}", args.addr.0, args.addr.1, args.wait));
+    // all listeners share one segment
+    let page_nodes = match listeners.first() {
+        Some(listener) => format_page_nodes(&listener.page_nodes()?),
+        None => String::new(),
+    };
+    if !page_nodes.is_empty() {
+        args.format.info(format_args!("pages of shared memory on nodes {}", page_nodes));
+    }
+    let reporter = Arc::new(Reporter{
+        printer: Printer::new(args.format),
+        host: HostInfo::collect(),
+        page_nodes,
+        next_cpu: AtomicUsize::new(0),
+        args,
+    });
+    // use one thread to serve each listener
+    let handles: Vec<_> = listeners.into_iter()
+        .map(|listener| {
+            let reporter = Arc::clone(&reporter);
+            thread::spawn(move || {
+                reporter.pin_next_cpu()?;
+                serve_fn(listener, &reporter)
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap()?;
+    }
+    Ok(())
+}
+
+fn serve_listener<L: Listener>(mut listener: L, reporter: &Arc<Reporter>) -> Result<()> {
+    loop {
+        let (conn, peer) = match accept(&mut listener, reporter) {
+            Some(res) => res?,
+            None => continue,
+        };
+        // use current thread to handle connection
+        handle(conn, &peer, reporter)?;
+    }
+}
+
+/// Serve socket connections in mode of arguments, all connections are
+/// served on current thread in epoll mode.
+fn serve_socket<L, C>(listener: L, reporter: &Arc<Reporter>) -> Result<()>
+where
+    L: for<'a> Listener<Conn<'a> = C> + EpollListener + 'static,
+    C: Connection + Send + 'static,
+{
+    match reporter.args.mode {
+        ServeMode::Epoll{busy_poll} => {
+            // no yield in event loop, and failure of one connection does
+            // not stop others
+            serve_epoll(listener, busy_poll, |peer, res, dur| match res {
+                Ok(sum) => reporter.disconnected(peer, sum, dur, 0),
+                Err(e) => eprintln!("connection of {} failed: {}", peer, e),
+            })?;
+            Ok(())
         }
-        ConnKind::Shm => {
-            unsafe {
-                let shmem = ShmemConf::new().size(4096).flink(&args.addr.1).create()?;
-                let raw_ptr = shmem.as_ptr();
-                let guard = CabinGuard;
-                let cabin: &Cabin<u64, u64> = Cabin::new(raw_ptr, &guard)?;
-                let mut client_id = 0;
-                let mut sum = 0;
-                let mut resp_written = false;
-                let mut inst = Instant::now();
-                let mut yield_num = 0usize;
-                loop {
-                    match cabin.load_state(Ordering::Acquire) {
-                        CabinState::AcceptingSpin => {
-                            let backoff = Backoff::new();
-                            backoff.snooze();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::AcceptingSpin {
-                                if backoff.is_completed() {
-                                    // try yield current thread
-                                    _ = cabin.cas_state(CabinState::AcceptingSpin, CabinState::AcceptingYield);
-                                    break
-                                } else {
-                                    backoff.snooze();
-                                }
-                            }
-                        }
-                        CabinState::AcceptingYield => {
-                            yield_num += 1;
-                            // blocking wait
-                            let lg = cabin.lock().unwrap();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::AcceptingYield {
-                                lg.wait()?;
-                            }
-                        }
-                        CabinState::Connecting => {
-                            client_id = cabin.id();
-                            inst = Instant::now();
-                            // transfer state to WAIT_REQ
-                            _ = cabin.cas_state(CabinState::Connecting, CabinState::WaitReqSpin);
-                        }
-                        CabinState::WaitReqSpin => {
-                            let backoff = Backoff::new();
-                            backoff.snooze();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::WaitReqSpin {
-                                if !args.spin_only && backoff.is_completed() {
-                                    // try yield current thread
-                                    _ = cabin.cas_state(CabinState::WaitReqSpin, CabinState::WaitReqYield);
-                                    break
-                                } else {
-                                    backoff.snooze();
-                                }
-                            }
-                        }
-                        CabinState::WaitReqYield => {
-                            yield_num += 1;
-                            // blocking wait
-                            let lg = cabin.lock().unwrap();
-                            while cabin.load_state(Ordering::Acquire) == CabinState::WaitReqYield {
-                                lg.wait()?;
-                            }
-                        }
-                        CabinState::WaitRespSpin => {
-                            let req = cabin.req();
-                            sum += req;
-                            cabin.set_resp(sum);
-                            resp_written = true;
-                            // transfer state to WAIT_REQ
-                            if cabin.cas_state(CabinState::WaitRespSpin, CabinState::WaitReqSpin).is_ok() {
-                                resp_written = false; // reset the flag so next time write new response
-                            }
-                        }
-                        CabinState::WaitRespYield => {
-                            if !resp_written {
-                                let req = cabin.req();
-                                sum += req;
-                                cabin.set_resp(sum);
-                            }
-                            let lg = cabin.lock().unwrap();
-                            assert!(cabin.cas_state(CabinState::WaitRespYield, CabinState::WaitReqSpin).is_ok());
-                            resp_written = false;
-                            lg.signal().unwrap();
-                        }
-                        CabinState::Disconnected => {
-                            let dur = inst.elapsed();
-                            println!("disconnected from client {}, sum is {}, duration is {:?}, yields is {}", client_id, sum, dur, yield_num);
-                            client_id = 0;
-                            sum = 0;
-                            yield_num = 0;
-                            inst = Instant::now();
-                            // transfer state to ACCEPTING
-                            _ = cabin.cas_state(CabinState::Disconnected, CabinState::AcceptingSpin);
+        _ => serve_threads(listener, reporter),
+    }
+}
+
+/// Serve socket connections in mode of arguments, connection is moved
+/// to another thread in thread and pool mode.
+fn serve_threads<L, C>(mut listener: L, reporter: &Arc<Reporter>) -> Result<()>
+where
+    L: for<'a> Listener<Conn<'a> = C> + 'static,
+    C: Connection + Send + 'static,
+{
+    match reporter.args.mode {
+        ServeMode::Serial => serve_listener(listener, reporter),
+        ServeMode::Thread => loop {
+            let (conn, peer) = listener.accept()?;
+            let reporter = Arc::clone(reporter);
+            thread::spawn(move || {
+                if let Err(e) = reporter.pin_next_cpu() {
+                    eprintln!("connection of {} failed: {}", peer, e);
+                    return
+                }
+                handle_in_worker(conn, &peer, &reporter)
+            });
+        },
+        ServeMode::Pool(workers) => {
+            let (tx, rx) = mpsc::channel::<(C, String)>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..workers {
+                let (rx, reporter) = (Arc::clone(&rx), Arc::clone(reporter));
+                thread::spawn(move || {
+                    if let Err(e) = reporter.pin_next_cpu() {
+                        eprintln!("worker failed: {}", e);
+                        return
+                    }
+                    loop {
+                        // lock is released once a connection is received
+                        let res = rx.lock().unwrap().recv();
+                        match res {
+                            Ok((conn, peer)) => handle_in_worker(conn, &peer, &reporter),
+                            Err(_) => return,
                         }
                     }
+                });
+            }
+            loop {
+                // workers only exit when sender is dropped
+                if tx.send(listener.accept()?).is_err() {
+                    anyhow::bail!("workers exited");
                 }
             }
         }
+        ServeMode::Epoll{..} => anyhow::bail!("serve mode {} only applies to tcp and unix", reporter.args.mode),
+    }
+}
+
+/// Accept a connection, returns None if client is dead before connected.
+fn accept<'a, L: Listener>(listener: &'a mut L, reporter: &Reporter) -> Option<Result<(L::Conn<'a>, String)>> {
+    match listener.accept() {
+        Ok(res) => Some(Ok(res)),
+        Err(Error::OwnerDead | Error::ClientDead) => {
+            reporter.args.format.info(format_args!("client is dead before connected, reset cabin"));
+            None
+        }
+        Err(e) => Some(Err(e.into())),
+    }
+}
+
+/// Serve the connection until client disconnects, and report its result.
+fn handle<C: Connection>(mut conn: C, peer: &str, reporter: &Reporter) -> Result<()> {
+    let inst = Instant::now();
+    match server_conn(&mut conn) {
+        Ok(sum) => {
+            let dur = inst.elapsed();
+            reporter.disconnected(peer, sum, dur, conn.yields());
+        }
+        Err(Error::OwnerDead | Error::ClientDead) => {
+            reporter.args.format.info(format_args!("{} is dead, reset cabin", peer));
+        }
+        // bad frame of one client does not stop server
+        Err(e @ Error::PayloadTooLarge(..)) => eprintln!("connection of {} failed: {}", peer, e),
+        Err(e) => return Err(e.into()),
     }
     Ok(())
 }
 
+/// Failure of one connection does not stop other workers.
+fn handle_in_worker<C: Connection>(conn: C, peer: &str, reporter: &Reporter) {
+    if let Err(e) = handle(conn, peer, reporter) {
+        eprintln!("connection of {} failed: {}", peer, e);
+    }
+}
+
+/// Reporter prints result of each session, shared by all serving threads.
+struct Reporter {
+    args: SvrArgs,
+    printer: Printer,
+    host: HostInfo,
+    /// Number of pages on each NUMA node, empty for sockets.
+    page_nodes: String,
+    /// Index of cpu to pin next serving thread.
+    next_cpu: AtomicUsize,
+}
+
+impl Reporter {
+    /// Pin current serving thread to next cpu of the list in round robin.
+    fn pin_next_cpu(&self) -> Result<()> {
+        let cpus = &self.args.cpus;
+        if cpus.is_empty() {
+            return Ok(())
+        }
+        let cpu = cpus[self.next_cpu.fetch_add(1, Ordering::Relaxed) % cpus.len()];
+        pin_cpu(cpu)?;
+        self.args.format.info(format_args!("serving thread pinned to cpu {}", cpu));
+        Ok(())
+    }
+
+    fn disconnected(&self, peer: &str, sum: u64, dur: Duration, yields: usize) {
+        if self.args.format == OutputFormat::Text {
+            println!("disconnected from {}, sum is {}, duration is {:?}, yields is {}", peer, sum, dur, yields);
+            return
+        }
+        let mut record = Record::new();
+        record.push("transport", self.args.addr.0.scheme());
+        record.push("addr", self.args.addr.1.as_str());
+        record.push("peer", peer);
+        record.push("spin_only", self.args.spin_only);
+        record.push("sqpoll", self.args.sqpoll);
+        record.push("slots", self.args.slots);
+        record.push("mode", self.args.mode.to_string());
+        record.push("wait", format!("{:?}", self.args.wait).to_lowercase());
+        // string so that unpinned run is "none"
+        let cpus: Vec<_> = self.args.cpus.iter().map(|cpu| cpu.to_string()).collect();
+        record.push("cpu", if cpus.is_empty() { "none".to_string() } else { cpus.join(",") });
+        record.push("mem_node", self.args.node.map_or_else(|| "none".to_string(), |node| node.to_string()));
+        record.push("page_nodes", self.page_nodes.as_str());
+        record.push("sum", sum);
+        record.push("duration_ns", dur);
+        record.push("yields", yields);
+        record.push_host(&self.host);
+        self.printer.print(&record);
+    }
+}
+
 #[derive(Debug)]
 pub struct SvrArgs {
     pub addr: (ConnKind, String),
     pub spin_only: bool,
+    /// Use SQPOLL for io_uring transports.
+    pub sqpoll: bool,
+    pub slots: usize,
+    pub wait: WaitBackend,
+    pub format: OutputFormat,
+    /// Serving threads are pinned to these cpus in round robin.
+    pub cpus: Vec<usize>,
+    pub node: Option<usize>,
+    pub mode: ServeMode,
 }
 
 impl SvrArgs {
@@ -145,6 +274,13 @@ impl SvrArgs {
         let mut parser = lexopt::Parser::from_env();
         let mut addr = None;
         let mut spin_only = false;
+        let mut sqpoll = false;
+        let mut slots = 1;
+        let mut wait = WaitBackend::Pthread;
+        let mut format = OutputFormat::Text;
+        let mut cpus = vec![];
+        let mut node = None;
+        let mut mode = ServeMode::Serial;
         while let Some(arg) = parser.next()? {
             match arg {
                 Short('a') | Long("addr") => {
@@ -153,9 +289,33 @@ impl SvrArgs {
                 Short('s') | Long("spin-only") => {
                     spin_only = parser.value()?.parse()?
                 }
+                Long("sqpoll") => {
+                    sqpoll = parser.value()?.parse()?
+                }
+                Short('l') | Long("slots") => {
+                    slots = parser.value()?.parse()?;
+                    if slots == 0 {
+                        anyhow::bail!("--slots must be at least 1");
+                    }
+                }
+                Short('w') | Long("wait") => {
+                    wait = parser.value()?.parse()?
+                }
+                Short('f') | Long("format") => {
+                    format = parser.value()?.parse()?
+                }
+                Short('c') | Long("cpu") => {
+                    cpus = parse_cpu_list(&parser.value()?.to_string_lossy())?
+                }
+                Short('m') | Long("mem-node") => {
+                    node = Some(parser.value()?.parse()?)
+                }
+                Long("mode") => {
+                    mode = parser.value()?.parse()?
+                }
                 _ => return Err(arg.unexpected().into())
             }
         }
-        Ok(SvrArgs{addr: addr.unwrap_or_else(|| parse_conn_kind("tcp:127.0.0.1:9001").unwrap()), spin_only})
+        Ok(SvrArgs{addr: addr.unwrap_or_else(|| parse_conn_kind("tcp:127.0.0.1:9001").unwrap()), spin_only, sqpoll, slots, wait, format, cpus, node, mode})
     }
 }
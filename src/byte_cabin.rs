@@ -0,0 +1,141 @@
+use std::mem::size_of;
+use std::ops::Deref;
+use std::slice;
+use crate::{Cabin, CabinGuard, Error, Result, SegmentKind, WaitBackend};
+use crate::header::ShmHeader;
+
+/// ByteCabin exchanges variable-length byte payloads instead of fixed values.
+/// The inner cabin stores length of request and response, and two buffers
+/// of `capacity` bytes follow the header in memory: request buffer first,
+/// then response buffer.
+/// State machine and locking are same as [`Cabin`], accessible via deref.
+#[repr(C)]
+pub struct ByteCabin {
+    cabin: Cabin<usize, usize>,
+    capacity: usize,
+}
+
+impl ByteCabin {
+    /// Returns memory size required to hold cabin with given capacity.
+    #[inline]
+    pub fn mem_size(capacity: usize) -> usize {
+        ShmHeader::mem_size::<Self>(size_of::<Self>() + capacity * 2)
+    }
+
+    /// Initialize a byte cabin with given capacity in given memory.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to zeroed shared memory of at least
+    /// [`ByteCabin::mem_size`] bytes, and must outlive the guard.
+    #[inline]
+    pub unsafe fn new(mem: *mut u8, capacity: usize, backend: WaitBackend, _guard: &CabinGuard) -> Result<&Self> {
+        let (header, ptr) = ShmHeader::init::<u8, u8, Self>(mem, SegmentKind::ByteCabin);
+        Cabin::<usize, usize>::init(ptr, backend)?;
+        let cabin = &mut *(ptr as *mut Self);
+        cabin.capacity = capacity;
+        header.mark_initialized();
+        Ok(cabin)
+    }
+
+    /// Attach to a byte cabin already initialized by another process.
+    /// Header is validated so that stale segment is rejected, and `len`
+    /// of the mapping must hold both buffers of recorded capacity.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to shared memory of `len` bytes, which is at least
+    /// header size, and must outlive the guard.
+    #[inline]
+    pub unsafe fn from_existing(mem: *mut u8, len: usize, _guard: &CabinGuard) -> Result<&Self> {
+        let (_, ptr) = ShmHeader::attach::<u8, u8, Self>(mem, SegmentKind::ByteCabin)?;
+        let cabin = &*(ptr as *const Self);
+        // check half of length first so that size does not overflow
+        if cabin.capacity > len / 2 || Self::mem_size(cabin.capacity) > len {
+            return Err(Error::SegmentTooSmall)
+        }
+        Ok(cabin)
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    fn buf_ptr(&self, offset: usize) -> *mut u8 {
+        unsafe { (self as *const Self as *mut u8).add(size_of::<Self>() + offset) }
+    }
+
+    #[inline]
+    pub fn req_len(&self) -> usize {
+        self.cabin.req()
+    }
+
+    /// Set length of request, panics if exceeds capacity.
+    #[inline]
+    pub fn set_req_len(&self, len: usize) {
+        assert!(len <= self.capacity);
+        self.cabin.set_req(len)
+    }
+
+    /// Returns request payload of current length, length set by peer
+    /// is clamped to capacity.
+    #[inline]
+    pub fn req(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buf_ptr(0), self.req_len().min(self.capacity)) }
+    }
+
+    /// Returns whole request buffer of capacity, so that client can
+    /// write payload in place and then call [`ByteCabin::set_req_len`].
+    ///
+    /// # Safety
+    ///
+    /// Caller must be the only writer of request according to cabin state,
+    /// and must not hold other references to the request buffer.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn req_mut(&self) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.buf_ptr(0), self.capacity)
+    }
+
+    #[inline]
+    pub fn resp_len(&self) -> usize {
+        self.cabin.resp()
+    }
+
+    /// Set length of response, panics if exceeds capacity.
+    #[inline]
+    pub fn set_resp_len(&self, len: usize) {
+        assert!(len <= self.capacity);
+        self.cabin.set_resp(len)
+    }
+
+    /// Returns response payload of current length, length set by peer
+    /// is clamped to capacity.
+    #[inline]
+    pub fn resp(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buf_ptr(self.capacity), self.resp_len().min(self.capacity)) }
+    }
+
+    /// Returns whole response buffer of capacity, so that server can
+    /// write payload in place and then call [`ByteCabin::set_resp_len`].
+    ///
+    /// # Safety
+    ///
+    /// Caller must be the only writer of response according to cabin state,
+    /// and must not hold other references to the response buffer.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn resp_mut(&self) -> &mut [u8] {
+        slice::from_raw_parts_mut(self.buf_ptr(self.capacity), self.capacity)
+    }
+}
+
+impl Deref for ByteCabin {
+    type Target = Cabin<usize, usize>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.cabin
+    }
+}
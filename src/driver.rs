@@ -0,0 +1,516 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use crossbeam_utils::Backoff;
+use crate::{Cabin, CabinState, Error, Result};
+
+/// Statistics of one client session served by [`CabinServer`].
+#[derive(Debug, Clone, Copy)]
+pub struct SessionStats {
+    pub client_id: u32,
+    pub requests: u64,
+    pub duration: Duration,
+    pub yields: usize,
+}
+
+/// CabinServer drives server side of cabin state machine.
+/// `C` is any handle of cabin, e.g. `&Cabin` or [`ShmCabin`](crate::ShmCabin).
+pub struct CabinServer<C> {
+    pub(crate) cabin: C,
+    spin_only: bool,
+    // request is received but not yet completed.
+    pending: bool,
+    yields: usize,
+}
+
+impl<C, T, U> CabinServer<C>
+where
+    C: Deref<Target = Cabin<T, U>>,
+    T: Copy,
+{
+    /// Create a server on the cabin, if `spin_only` is false, server
+    /// yields when waiting for request too long.
+    #[inline]
+    pub fn new(cabin: C, spin_only: bool) -> Self {
+        CabinServer{cabin, spin_only, pending: false, yields: 0}
+    }
+
+    /// Returns how many times server yielded in current session.
+    #[inline]
+    pub fn yields(&self) -> usize {
+        self.yields
+    }
+
+    /// Accept one client and serve its requests with the handler until
+    /// it disconnects, then reset the cabin for next client.
+    /// If client died, the cabin is also reset and [`Error::ClientDead`]
+    /// or [`Error::OwnerDead`] is returned.
+    #[inline]
+    pub fn serve<F: FnMut(T) -> U>(&mut self, mut handler: F) -> Result<SessionStats> {
+        let client_id = self.accept()?;
+        let inst = Instant::now();
+        let mut requests = 0;
+        while let Some(req) = self.recv()? {
+            self.reply(handler(req))?;
+            requests += 1;
+        }
+        Ok(SessionStats{client_id, requests, duration: inst.elapsed(), yields: self.yields})
+    }
+
+    /// Wait until a client connects, returns client id.
+    #[inline]
+    pub fn accept(&mut self) -> Result<u32> {
+        self.pending = false;
+        self.yields = 0;
+        let res = self.wait_connecting();
+        self.recover(res)
+    }
+
+    /// Wait for next request, returns None if client disconnected, and
+    /// the cabin is reset for next client.
+    /// Request received before but not replied is completed without
+    /// new response.
+    #[inline]
+    pub fn recv(&mut self) -> Result<Option<T>> {
+        let res = self.wait_request();
+        self.recover(res)
+    }
+
+    /// Reply to the request just received.
+    #[inline]
+    pub fn reply(&mut self, resp: U) -> Result<()> {
+        self.cabin.set_resp(resp);
+        let res = self.complete();
+        self.recover(res)
+    }
+
+    /// Client crashed, recover the cabin so that it does not wedge the server.
+    #[inline]
+    fn recover<R>(&self, res: Result<R>) -> Result<R> {
+        if let Err(Error::OwnerDead | Error::ClientDead) = res {
+            self.cabin.reset();
+        }
+        res
+    }
+
+    fn wait_connecting(&mut self) -> Result<u32> {
+        let cabin = &*self.cabin;
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::AcceptingSpin => {
+                    let backoff = Backoff::new();
+                    backoff.snooze();
+                    while cabin.load_state(Ordering::Acquire) == CabinState::AcceptingSpin {
+                        if backoff.is_completed() {
+                            // try yield current thread
+                            _ = cabin.cas_state(CabinState::AcceptingSpin, CabinState::AcceptingYield);
+                            break
+                        } else {
+                            backoff.snooze();
+                        }
+                    }
+                }
+                CabinState::AcceptingYield => {
+                    self.yields += 1;
+                    // blocking wait
+                    cabin.wait_while(CabinState::AcceptingYield)?;
+                }
+                CabinState::Connecting => {
+                    let client_id = cabin.id();
+                    // transfer state to WAIT_REQ
+                    if cabin.cas_state(CabinState::Connecting, CabinState::WaitReqSpin).is_ok() {
+                        return Ok(client_id)
+                    }
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+    }
+
+    fn wait_request(&mut self) -> Result<Option<T>> {
+        if self.pending {
+            self.complete()?;
+        }
+        let cabin = &*self.cabin;
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::WaitReqSpin => {
+                    let backoff = Backoff::new();
+                    backoff.snooze();
+                    while cabin.load_state(Ordering::Acquire) == CabinState::WaitReqSpin {
+                        if backoff.is_completed() {
+                            if !self.spin_only {
+                                // try yield current thread
+                                _ = cabin.cas_state(CabinState::WaitReqSpin, CabinState::WaitReqYield);
+                                break
+                            }
+                            if !cabin.client_alive() {
+                                return Err(Error::ClientDead)
+                            }
+                        }
+                        backoff.snooze();
+                    }
+                }
+                CabinState::WaitReqYield => {
+                    self.yields += 1;
+                    // blocking wait
+                    cabin.wait_while(CabinState::WaitReqYield)?;
+                }
+                CabinState::WaitRespSpin | CabinState::WaitRespYield => {
+                    self.pending = true;
+                    return Ok(Some(cabin.req()))
+                }
+                CabinState::Disconnected => {
+                    // transfer state to ACCEPTING and release the cabin for next client
+                    cabin.reset();
+                    return Ok(None)
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+    }
+
+    /// Hand over the cabin back to client.
+    fn complete(&mut self) -> Result<()> {
+        let cabin = &*self.cabin;
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::WaitRespSpin => {
+                    // transfer state to WAIT_REQ
+                    if cabin.cas_state(CabinState::WaitRespSpin, CabinState::WaitReqSpin).is_ok() {
+                        break
+                    }
+                }
+                CabinState::WaitRespYield => {
+                    cabin.switch_and_wake(CabinState::WaitRespYield, CabinState::WaitReqSpin)?;
+                    break
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+        self.pending = false;
+        Ok(())
+    }
+}
+
+/// CabinClient drives client side of cabin state machine.
+/// The cabin must be claimed before connecting.
+/// `C` is any handle of cabin, e.g. `&Cabin` or [`ShmCabin`](crate::ShmCabin).
+pub struct CabinClient<C, T, U>
+where
+    C: Deref<Target = Cabin<T, U>>,
+{
+    cabin: C,
+    spin_only: bool,
+    connected: bool,
+    // request is sent but not yet completed by server.
+    pending: bool,
+    yields: usize,
+    _marker: PhantomData<fn() -> (T, U)>,
+}
+
+impl<C, T, U> CabinClient<C, T, U>
+where
+    C: Deref<Target = Cabin<T, U>>,
+    U: Copy,
+{
+    /// Connect to server with client id, if `spin_only` is false, client
+    /// yields when waiting for response too long.
+    /// Server liveness is checked while waiting, and [`Error::ServerDead`]
+    /// is returned if server process died.
+    #[inline]
+    pub fn connect(cabin: C, id: u32, spin_only: bool) -> Result<Self> {
+        cabin.set_id(id);
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::AcceptingSpin => {
+                    if cabin.cas_state(CabinState::AcceptingSpin, CabinState::Connecting).is_ok() {
+                        break
+                    }
+                }
+                CabinState::AcceptingYield => {
+                    cabin.switch_and_wake(CabinState::AcceptingYield, CabinState::Connecting)?;
+                    break
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+        // wait for server to accept, it may have died without resetting the cabin
+        let backoff = Backoff::new();
+        while cabin.load_state(Ordering::Acquire) == CabinState::Connecting {
+            if backoff.is_completed() && !cabin.server_alive() {
+                return Err(Error::ServerDead)
+            }
+            backoff.snooze();
+        }
+        Ok(CabinClient{cabin, spin_only, connected: true, pending: false, yields: 0, _marker: PhantomData})
+    }
+
+    /// Send request and wait for response.
+    #[inline]
+    pub fn call(&mut self, req: T) -> Result<U> {
+        self.send(req)?;
+        self.recv()
+    }
+
+    /// Wait for previous request to complete if any, so that client owns
+    /// request of the cabin, e.g. to write payload of byte cabin in place.
+    #[inline]
+    pub fn wait_idle(&mut self) -> Result<()> {
+        if self.pending {
+            self.recv()?;
+        }
+        Ok(())
+    }
+
+    /// Send request without reading response.
+    /// If previous request is not completed, wait for it first.
+    #[inline]
+    pub fn send(&mut self, req: T) -> Result<()> {
+        self.wait_idle()?;
+        let cabin = &*self.cabin;
+        cabin.set_req(req);
+        // hand over request to server
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::WaitReqSpin => {
+                    if cabin.cas_state(CabinState::WaitReqSpin, CabinState::WaitRespSpin).is_ok() {
+                        break
+                    }
+                }
+                CabinState::WaitReqYield => {
+                    cabin.switch_and_wake(CabinState::WaitReqYield, CabinState::WaitRespSpin)?;
+                    break
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+        self.pending = true;
+        Ok(())
+    }
+
+    /// Wait for response of request just sent.
+    #[inline]
+    pub fn recv(&mut self) -> Result<U> {
+        let cabin = &*self.cabin;
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::WaitRespSpin => {
+                    let backoff = Backoff::new();
+                    backoff.snooze();
+                    while cabin.load_state(Ordering::Acquire) == CabinState::WaitRespSpin {
+                        if backoff.is_completed() {
+                            if !self.spin_only {
+                                // try yield current thread
+                                _ = cabin.cas_state(CabinState::WaitRespSpin, CabinState::WaitRespYield);
+                                break
+                            }
+                            if !cabin.server_alive() {
+                                return Err(Error::ServerDead)
+                            }
+                        }
+                        backoff.snooze();
+                    }
+                }
+                CabinState::WaitRespYield => {
+                    self.yields += 1;
+                    // blocking wait
+                    cabin.wait_while(CabinState::WaitRespYield)?;
+                }
+                CabinState::WaitReqSpin | CabinState::WaitReqYield => {
+                    self.pending = false;
+                    return Ok(cabin.resp())
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+    }
+}
+
+impl<C, T, U> CabinClient<C, T, U>
+where
+    C: Deref<Target = Cabin<T, U>>,
+{
+    /// Returns how many times client yielded when waiting for response.
+    #[inline]
+    pub fn yields(&self) -> usize {
+        self.yields
+    }
+
+    #[inline]
+    pub fn cabin(&self) -> &C {
+        &self.cabin
+    }
+
+    /// Disconnect from server, the cabin is released by server afterwards.
+    /// Request not yet completed is still processed by server.
+    #[inline]
+    pub fn disconnect(&mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(())
+        }
+        let cabin = &*self.cabin;
+        let backoff = Backoff::new();
+        loop {
+            match cabin.load_state(Ordering::Acquire) {
+                CabinState::WaitReqSpin => {
+                    if cabin.cas_state(CabinState::WaitReqSpin, CabinState::Disconnected).is_ok() {
+                        break
+                    }
+                }
+                CabinState::WaitReqYield => {
+                    cabin.switch_and_wake(CabinState::WaitReqYield, CabinState::Disconnected)?;
+                    break
+                }
+                // wait for server to complete pending request
+                CabinState::WaitRespSpin | CabinState::WaitRespYield if self.pending => {
+                    if backoff.is_completed() && !cabin.server_alive() {
+                        return Err(Error::ServerDead)
+                    }
+                    backoff.snooze()
+                }
+                _ => return Err(Error::UnknownState),
+            }
+        }
+        self.connected = false;
+        self.pending = false;
+        Ok(())
+    }
+}
+
+impl<C, T, U> Drop for CabinClient<C, T, U>
+where
+    C: Deref<Target = Cabin<T, U>>,
+{
+    fn drop(&mut self) {
+        _ = self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use libc::pid_t;
+    use crate::{dead_pid, CabinGuard, WaitBackend};
+
+    /// Run `f` with a cabin initialized in heap memory, as in a new segment.
+    fn with_cabin<F: FnOnce(&Cabin<u64, u64>)>(backend: WaitBackend, f: F) {
+        let mut mem = vec![0u8; Cabin::<u64, u64>::mem_size()];
+        let guard = CabinGuard;
+        let cabin = unsafe { Cabin::<u64, u64>::new(mem.as_mut_ptr(), backend, &guard).unwrap() };
+        f(cabin);
+        unsafe { cabin.destroy(); }
+    }
+
+    /// Serve one session of client sending `1..=num` on another thread.
+    fn serve_session(cabin: &Cabin<u64, u64>, id: u32, num: u64, spin_only: bool) -> SessionStats {
+        thread::scope(|s| {
+            let server = s.spawn(|| CabinServer::new(cabin, spin_only).serve(|req| req * 2));
+            assert!(cabin.try_claim());
+            let mut client = CabinClient::connect(cabin, id, spin_only).unwrap();
+            for req in 1..=num {
+                assert_eq!(client.call(req).unwrap(), req * 2);
+            }
+            client.disconnect().unwrap();
+            server.join().unwrap().unwrap()
+        })
+    }
+
+    fn assert_sessions(backend: WaitBackend, spin_only: bool) {
+        with_cabin(backend, |cabin| {
+            for id in [1, 2] {
+                let stats = serve_session(cabin, id, 100, spin_only);
+                assert_eq!(stats.client_id, id);
+                assert_eq!(stats.requests, 100);
+                // cabin is reset and released for next client
+                assert_eq!(cabin.load_state(Ordering::Acquire), CabinState::AcceptingSpin);
+                assert!(cabin.try_claim());
+                cabin.release();
+            }
+        });
+    }
+
+    #[test]
+    fn test_sessions_spin_only() {
+        assert_sessions(WaitBackend::Pthread, true);
+    }
+
+    #[test]
+    fn test_sessions_pthread() {
+        assert_sessions(WaitBackend::Pthread, false);
+    }
+
+    #[test]
+    fn test_sessions_futex() {
+        assert_sessions(WaitBackend::Futex, false);
+    }
+
+    #[test]
+    fn test_send_then_recv() {
+        with_cabin(WaitBackend::Futex, |cabin| {
+            thread::scope(|s| {
+                let server = s.spawn(|| CabinServer::new(cabin, false).serve(|req| req + 1));
+                assert!(cabin.try_claim());
+                let mut client = CabinClient::connect(cabin, 1, false).unwrap();
+                client.send(1).unwrap();
+                // previous request is completed before next one is sent
+                client.send(2).unwrap();
+                assert_eq!(client.recv().unwrap(), 3);
+                // pending request is completed before disconnection
+                client.send(3).unwrap();
+                drop(client);
+                assert_eq!(server.join().unwrap().unwrap().requests, 3);
+            })
+        });
+    }
+
+    fn assert_client_dead(backend: WaitBackend, spin_only: bool) {
+        with_cabin(backend, |cabin| {
+            thread::scope(|s| {
+                let server = s.spawn(|| {
+                    let mut server = CabinServer::new(cabin, spin_only);
+                    server.accept().unwrap();
+                    server.recv()
+                });
+                assert!(cabin.try_claim());
+                let client = CabinClient::connect(cabin, 1, spin_only).unwrap();
+                // client dies without disconnecting
+                cabin.set_client_pid(dead_pid());
+                std::mem::forget(client);
+                assert!(matches!(server.join().unwrap(), Err(Error::ClientDead)));
+            });
+            assert_eq!(cabin.client_pid(), 0);
+            assert_eq!(cabin.load_state(Ordering::Acquire), CabinState::AcceptingSpin);
+            // next client is served
+            assert_eq!(serve_session(cabin, 2, 10, spin_only).requests, 10);
+        });
+    }
+
+    #[test]
+    fn test_recover_client_dead_spin_only() {
+        assert_client_dead(WaitBackend::Pthread, true);
+    }
+
+    #[test]
+    fn test_recover_client_dead_pthread() {
+        assert_client_dead(WaitBackend::Pthread, false);
+    }
+
+    #[test]
+    fn test_recover_client_dead_futex() {
+        assert_client_dead(WaitBackend::Futex, false);
+    }
+
+    #[test]
+    fn test_server_dead() {
+        with_cabin(WaitBackend::Pthread, |cabin| {
+            assert_eq!(cabin.server_pid(), std::process::id() as pid_t);
+            cabin.set_server_pid(dead_pid());
+            assert!(cabin.try_claim());
+            // nobody accepts the connection
+            assert!(matches!(CabinClient::connect(cabin, 1, true), Err(Error::ServerDead)));
+        });
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant};
+use crate::{Result, ServerAction, ServerState};
+
+/// Max number of events returned by one `epoll_wait`.
+const MAX_EVENTS: usize = 256;
+
+/// Size of buffer to read requests of all connections.
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+/// Listener of socket which can accept non-blocking streams.
+pub trait EpollListener: AsRawFd {
+    type Stream: Read + Write + AsRawFd;
+
+    /// Accept a connection, returns the stream and description of peer.
+    fn accept_stream(&self) -> io::Result<(Self::Stream, String)>;
+}
+
+impl EpollListener for TcpListener {
+    type Stream = TcpStream;
+
+    #[inline]
+    fn accept_stream(&self) -> io::Result<(Self::Stream, String)> {
+        let (stream, remote_addr) = self.accept()?;
+        stream.set_nodelay(true)?;
+        Ok((stream, format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+impl EpollListener for UnixListener {
+    type Stream = UnixStream;
+
+    #[inline]
+    fn accept_stream(&self) -> io::Result<(Self::Stream, String)> {
+        let (stream, remote_addr) = self.accept()?;
+        Ok((stream, format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+/// Epoll instance, closed when dropped.
+struct Epoll(RawFd);
+
+impl Epoll {
+    #[inline]
+    fn new() -> Result<Self> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into())
+        }
+        Ok(Epoll(fd))
+    }
+
+    /// Add, modify or delete interest of the fd, the fd is used as token.
+    #[inline]
+    fn ctl(&self, op: libc::c_int, fd: RawFd, events: u32) -> Result<()> {
+        let mut event = libc::epoll_event{events, u64: fd as u64};
+        if unsafe { libc::epoll_ctl(self.0, op, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error().into())
+        }
+        Ok(())
+    }
+
+    /// Wait for events, returns immediately if `busy_poll` is set.
+    #[inline]
+    fn wait(&self, events: &mut [libc::epoll_event], busy_poll: bool) -> Result<usize> {
+        let timeout = if busy_poll { 0 } else { -1 };
+        let n = unsafe { libc::epoll_wait(self.0, events.as_mut_ptr(), events.len() as libc::c_int, timeout) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                return Ok(0)
+            }
+            return Err(err.into())
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
+}
+
+/// Connection driven by events, requests are decoded incrementally
+/// as bytes arrive.
+struct EventConn<S> {
+    stream: S,
+    peer: String,
+    state: ServerState,
+    /// Bytes of value not completely received.
+    partial: [u8; 8],
+    partial_len: usize,
+    /// Length of payload frame being received, and bytes remaining.
+    payload: Option<(usize, usize)>,
+    /// Responses not written yet.
+    out: Vec<u8>,
+    /// Events registered in epoll.
+    interest: u32,
+    start: Instant,
+}
+
+impl<S: Read + Write> EventConn<S> {
+    /// Decode requests in bytes, and queue responses.
+    #[inline]
+    fn on_bytes(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if let Some((len, remaining)) = self.payload {
+                // skip payload
+                let n = remaining.min(buf.len());
+                buf = &buf[n..];
+                if n < remaining {
+                    self.payload = Some((len, remaining - n));
+                } else {
+                    self.payload = None;
+                    let resp = self.state.on_payload(len);
+                    self.out.extend_from_slice(&resp.to_le_bytes());
+                }
+                continue
+            }
+            let n = (8 - self.partial_len).min(buf.len());
+            self.partial[self.partial_len..self.partial_len + n].copy_from_slice(&buf[..n]);
+            self.partial_len += n;
+            buf = &buf[n..];
+            if self.partial_len < 8 {
+                break
+            }
+            self.partial_len = 0;
+            match self.state.on_value(u64::from_le_bytes(self.partial)) {
+                ServerAction::None => (),
+                ServerAction::Reply(resp) => self.out.extend_from_slice(&resp.to_le_bytes()),
+                ServerAction::Payload(0) => {
+                    let resp = self.state.on_payload(0);
+                    self.out.extend_from_slice(&resp.to_le_bytes());
+                }
+                ServerAction::Payload(len) => self.payload = Some((len, len)),
+            }
+        }
+    }
+
+    /// Write queued responses as much as possible, returns true if all
+    /// are written.
+    #[inline]
+    fn flush(&mut self) -> io::Result<bool> {
+        let mut written = 0;
+        while written < self.out.len() {
+            match self.stream.write(&self.out[written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.out.drain(..written);
+        Ok(self.out.is_empty())
+    }
+}
+
+/// Serve all connections of the listener on current thread with
+/// non-blocking sockets and epoll, using same protocol as
+/// [`server_conn`](crate::server_conn).
+/// Busy poll calls `epoll_wait` with zero timeout instead of blocking.
+/// `on_disconnect` is called with peer, sum or error, and duration of each
+/// connection when it disconnects or fails.
+#[inline]
+pub fn serve_epoll<L, F>(listener: L, busy_poll: bool, mut on_disconnect: F) -> Result<()>
+where
+    L: EpollListener,
+    F: FnMut(&str, Result<u64>, Duration),
+{
+    let epoll = Epoll::new()?;
+    let listener_fd = listener.as_raw_fd();
+    set_nonblocking(listener_fd)?;
+    epoll.ctl(libc::EPOLL_CTL_ADD, listener_fd, libc::EPOLLIN as u32)?;
+    let mut conns: HashMap<RawFd, EventConn<L::Stream>> = HashMap::new();
+    let mut events = vec![libc::epoll_event{events: 0, u64: 0}; MAX_EVENTS];
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    loop {
+        let n = epoll.wait(&mut events, busy_poll)?;
+        for event in &events[..n] {
+            let fd = event.u64 as RawFd;
+            if fd == listener_fd {
+                accept_all(&listener, &epoll, &mut conns)?;
+                continue
+            }
+            let Some(conn) = conns.get_mut(&fd) else { continue };
+            match on_event(conn, event.events, &mut buf) {
+                Ok(Some(want_write)) => {
+                    let mut interest = libc::EPOLLIN as u32;
+                    if want_write {
+                        interest |= libc::EPOLLOUT as u32;
+                    }
+                    // only modify when writability is wanted or not any more
+                    if interest != conn.interest {
+                        epoll.ctl(libc::EPOLL_CTL_MOD, fd, interest)?;
+                        conn.interest = interest;
+                    }
+                }
+                Ok(None) => {
+                    // closed by client
+                    let conn = conns.remove(&fd).unwrap();
+                    epoll.ctl(libc::EPOLL_CTL_DEL, fd, 0)?;
+                    on_disconnect(&conn.peer, Ok(conn.state.sum()), conn.start.elapsed());
+                }
+                Err(e) => {
+                    let conn = conns.remove(&fd).unwrap();
+                    epoll.ctl(libc::EPOLL_CTL_DEL, fd, 0)?;
+                    on_disconnect(&conn.peer, Err(e.into()), conn.start.elapsed());
+                }
+            }
+        }
+    }
+}
+
+/// Accept all pending connections.
+#[inline]
+fn accept_all<L: EpollListener>(listener: &L, epoll: &Epoll, conns: &mut HashMap<RawFd, EventConn<L::Stream>>) -> Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept_stream() {
+            Ok(res) => res,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let fd = stream.as_raw_fd();
+        set_nonblocking(fd)?;
+        epoll.ctl(libc::EPOLL_CTL_ADD, fd, libc::EPOLLIN as u32)?;
+        conns.insert(fd, EventConn{
+            stream,
+            peer,
+            state: ServerState::default(),
+            partial: [0; 8],
+            partial_len: 0,
+            payload: None,
+            out: Vec::new(),
+            interest: libc::EPOLLIN as u32,
+            start: Instant::now(),
+        });
+    }
+}
+
+/// Handle readiness of connection, returns whether responses are pending
+/// to write, or None if client disconnected.
+#[inline]
+fn on_event<S: Read + Write>(conn: &mut EventConn<S>, events: u32, buf: &mut [u8]) -> io::Result<Option<bool>> {
+    if events & (libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0 {
+        match conn.stream.read(buf) {
+            Ok(0) => return Ok(None),
+            Ok(n) => conn.on_bytes(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => (),
+            // client closed with unread responses, same as disconnection
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(!conn.flush()?))
+}
+
+#[inline]
+fn set_nonblocking(fd: RawFd) -> Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error().into())
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::ptr;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+use libc::{syscall, timespec, SYS_futex, FUTEX_WAIT, FUTEX_WAKE, EAGAIN, EINTR, ETIMEDOUT};
+use crate::{Error, Result};
+
+/// Block while value of the atomic equals `expected`, at most for the timeout.
+/// The futex is shared (not private) because peer is in another process.
+/// Spurious wakeups are possible, caller must re-check the value.
+#[inline]
+pub(crate) fn futex_wait(atomic: &AtomicU32, expected: u32, timeout: Duration) -> Result<()> {
+    let ts = timespec{
+        tv_sec: timeout.as_secs() as _,
+        tv_nsec: timeout.subsec_nanos() as _,
+    };
+    let res = unsafe {
+        syscall(SYS_futex, atomic.as_ptr(), FUTEX_WAIT, expected, &ts as *const timespec, ptr::null::<u32>(), 0)
+    };
+    if res != 0 {
+        let code = errno::errno().0;
+        // value already changed, interrupted by signal or timed out
+        if code != EAGAIN && code != EINTR && code != ETIMEDOUT {
+            return Err(Error::FailFutexWait(code))
+        }
+    }
+    Ok(())
+}
+
+/// Wake up all waiters blocked on the atomic.
+#[inline]
+pub(crate) fn futex_wake(atomic: &AtomicU32) -> Result<()> {
+    let res = unsafe {
+        syscall(SYS_futex, atomic.as_ptr(), FUTEX_WAKE, i32::MAX, ptr::null::<timespec>(), ptr::null::<u32>(), 0)
+    };
+    if res < 0 {
+        return Err(Error::FailFutexWake(errno::errno().0))
+    }
+    Ok(())
+}
@@ -0,0 +1,267 @@
+use std::any::type_name;
+use std::mem::{align_of, size_of};
+use std::sync::atomic::{AtomicU32, Ordering};
+use libc::pid_t;
+use crate::{pid_alive, Error, Result};
+
+/// Magic number at the start of every segment, "BENCHSHM" in little endian.
+pub const SHM_MAGIC: u64 = u64::from_le_bytes(*b"BENCHSHM");
+/// Version of shared memory protocol, bump it on any layout change.
+pub const SHM_VERSION: u32 = 2;
+
+/// Kind of object hosted in the segment.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Cabin = 1,
+    CabinPool = 2,
+    ByteCabin = 3,
+    RingChannel = 4,
+}
+
+/// ShmHeader is placed at the start of segment and describes the object
+/// following it, so that process attaching to the segment can verify
+/// it is built with same protocol and same request/response types.
+#[repr(C)]
+pub(crate) struct ShmHeader {
+    magic: u64,
+    version: u32,
+    kind: u32,
+    layout_hash: u64,
+    body_size: u32,
+    body_align: u32,
+    req_size: u32,
+    req_align: u32,
+    resp_size: u32,
+    resp_align: u32,
+    // process that created the segment.
+    server_pid: pid_t,
+    // set after the whole segment is initialized.
+    initialized: AtomicU32,
+}
+
+impl ShmHeader {
+    /// Returns memory size required to hold header and body of given size.
+    #[inline]
+    pub(crate) fn mem_size<B>(body_size: usize) -> usize {
+        // reserve alignment of header, in case memory is not aligned.
+        Self::align::<B>() + Self::body_offset::<B>() + body_size
+    }
+
+    /// Header is aligned to body as well, so that body following it is
+    /// aligned, e.g. cabins aligned to cache line.
+    #[inline]
+    fn align<B>() -> usize {
+        align_of::<Self>().max(align_of::<B>())
+    }
+
+    #[inline]
+    fn body_offset<B>() -> usize {
+        size_of::<Self>().next_multiple_of(align_of::<B>())
+    }
+
+    /// Write header describing body `B` with request `T` and response `U`,
+    /// returns header and pointer to body.
+    /// [`ShmHeader::mark_initialized`] must be called after body is initialized.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to writable memory of at least [`ShmHeader::mem_size`] bytes.
+    #[inline]
+    pub(crate) unsafe fn init<'a, T, U, B>(mem: *mut u8, kind: SegmentKind) -> (&'a Self, *mut u8) {
+        let padding = mem.align_offset(Self::align::<B>());
+        let ptr = mem.add(padding);
+        let header = &mut *(ptr as *mut Self);
+        header.magic = SHM_MAGIC;
+        header.version = SHM_VERSION;
+        header.kind = kind as u32;
+        header.layout_hash = layout_hash::<T, U, B>();
+        header.body_size = size_of::<B>() as u32;
+        header.body_align = align_of::<B>() as u32;
+        header.req_size = size_of::<T>() as u32;
+        header.req_align = align_of::<T>() as u32;
+        header.resp_size = size_of::<U>() as u32;
+        header.resp_align = align_of::<U>() as u32;
+        header.server_pid = std::process::id() as pid_t;
+        (header, ptr.add(Self::body_offset::<B>()))
+    }
+
+    /// Validate header against body `B` with request `T` and response `U`,
+    /// returns header and pointer to body.
+    /// Segment left by a dead server is rejected with [`Error::ServerDead`].
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to readable memory of at least header size.
+    #[inline]
+    pub(crate) unsafe fn attach<'a, T, U, B>(mem: *mut u8, kind: SegmentKind) -> Result<(&'a Self, *mut u8)> {
+        let padding = mem.align_offset(Self::align::<B>());
+        let ptr = mem.add(padding);
+        let header = &*(ptr as *const Self);
+        if header.initialized.load(Ordering::Acquire) == 0 {
+            return Err(Error::NotInitialized)
+        }
+        if header.magic != SHM_MAGIC {
+            return Err(Error::BadMagic(header.magic))
+        }
+        if header.version != SHM_VERSION {
+            return Err(Error::VersionMismatch(SHM_VERSION, header.version))
+        }
+        if header.kind != kind as u32 {
+            return Err(Error::SegmentKindMismatch)
+        }
+        if header.body_size != size_of::<B>() as u32
+            || header.req_size != size_of::<T>() as u32
+            || header.resp_size != size_of::<U>() as u32 {
+            return Err(Error::SizeMismatch)
+        }
+        if header.body_align != align_of::<B>() as u32
+            || header.req_align != align_of::<T>() as u32
+            || header.resp_align != align_of::<U>() as u32 {
+            return Err(Error::AlignMismatch)
+        }
+        if header.layout_hash != layout_hash::<T, U, B>() {
+            return Err(Error::LayoutHashMismatch)
+        }
+        if !pid_alive(header.server_pid) {
+            return Err(Error::ServerDead)
+        }
+        Ok((header, ptr.add(Self::body_offset::<B>())))
+    }
+
+    #[inline]
+    pub(crate) fn mark_initialized(&self) {
+        self.initialized.store(1, Ordering::Release)
+    }
+}
+
+/// FNV-1a hash of type names, sizes and alignments.
+#[inline]
+fn layout_hash<T, U, B>() -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for (name, size, align) in [
+        (type_name::<T>(), size_of::<T>(), align_of::<T>()),
+        (type_name::<U>(), size_of::<U>(), align_of::<U>()),
+        (type_name::<B>(), size_of::<B>(), align_of::<B>()),
+    ] {
+        feed(name.as_bytes());
+        feed(&size.to_le_bytes());
+        feed(&align.to_le_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_pid;
+
+    /// Body aligned to cache line like cabins.
+    #[repr(C, align(64))]
+    struct Body([u8; 64]);
+
+    /// Memory deliberately misaligned by one byte, so that header padding
+    /// is exercised.
+    struct Mem(Vec<u8>);
+
+    impl Mem {
+        fn new() -> Self {
+            Mem(vec![0; ShmHeader::mem_size::<Body>(size_of::<Body>()) + 1])
+        }
+
+        fn ptr(&mut self) -> *mut u8 {
+            unsafe { self.0.as_mut_ptr().add(1) }
+        }
+
+        /// Initialize header describing `Body` with `u64` request and response.
+        fn init(&mut self) -> *mut ShmHeader {
+            let mem = self.ptr();
+            unsafe {
+                let (header, _) = ShmHeader::init::<u64, u64, Body>(mem, SegmentKind::Cabin);
+                header.mark_initialized();
+                mem.add(mem.align_offset(ShmHeader::align::<Body>())) as *mut ShmHeader
+            }
+        }
+
+        fn attach<T, U>(&mut self, kind: SegmentKind) -> Result<*mut u8> {
+            unsafe { ShmHeader::attach::<T, U, Body>(self.ptr(), kind).map(|(_, body)| body) }
+        }
+    }
+
+    #[test]
+    fn test_header_body_aligned() {
+        let mut mem = Mem::new();
+        let end = mem.ptr() as usize + mem.0.len() - 1;
+        let (_, body) = unsafe { ShmHeader::init::<u64, u64, Body>(mem.ptr(), SegmentKind::Cabin) };
+        assert_eq!(body.align_offset(align_of::<Body>()), 0);
+        assert!(body as usize + size_of::<Body>() <= end);
+        mem.init();
+        assert_eq!(mem.attach::<u64, u64>(SegmentKind::Cabin).unwrap(), body);
+    }
+
+    #[test]
+    fn test_header_not_initialized() {
+        let mut mem = Mem::new();
+        assert!(matches!(mem.attach::<u64, u64>(SegmentKind::Cabin), Err(Error::NotInitialized)));
+    }
+
+    #[test]
+    fn test_header_bad_magic() {
+        let mut mem = Mem::new();
+        unsafe { (*mem.init()).magic = 1 };
+        assert!(matches!(mem.attach::<u64, u64>(SegmentKind::Cabin), Err(Error::BadMagic(1))));
+    }
+
+    #[test]
+    fn test_header_version_mismatch() {
+        let mut mem = Mem::new();
+        unsafe { (*mem.init()).version = SHM_VERSION + 1 };
+        assert!(matches!(
+            mem.attach::<u64, u64>(SegmentKind::Cabin),
+            Err(Error::VersionMismatch(SHM_VERSION, v)) if v == SHM_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_header_kind_mismatch() {
+        let mut mem = Mem::new();
+        mem.init();
+        assert!(matches!(mem.attach::<u64, u64>(SegmentKind::RingChannel), Err(Error::SegmentKindMismatch)));
+    }
+
+    #[test]
+    fn test_header_size_mismatch() {
+        let mut mem = Mem::new();
+        mem.init();
+        assert!(matches!(mem.attach::<u32, u64>(SegmentKind::Cabin), Err(Error::SizeMismatch)));
+    }
+
+    #[test]
+    fn test_header_align_mismatch() {
+        let mut mem = Mem::new();
+        mem.init();
+        // same size as u64 but aligned to byte
+        assert!(matches!(mem.attach::<[u8; 8], u64>(SegmentKind::Cabin), Err(Error::AlignMismatch)));
+    }
+
+    #[test]
+    fn test_header_layout_hash_mismatch() {
+        let mut mem = Mem::new();
+        mem.init();
+        // same size and alignment but different type
+        assert!(matches!(mem.attach::<i64, u64>(SegmentKind::Cabin), Err(Error::LayoutHashMismatch)));
+    }
+
+    #[test]
+    fn test_header_server_dead() {
+        let mut mem = Mem::new();
+        unsafe { (*mem.init()).server_pid = dead_pid() };
+        assert!(matches!(mem.attach::<u64, u64>(SegmentKind::Cabin), Err(Error::ServerDead)));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::time::Duration;
+use hdrhistogram::Histogram;
+
+/// Highest latency can be recorded, larger value is saturated.
+const MAX_LATENCY_NANOS: u64 = 60_000_000_000;
+
+/// Latency records duration of each request in a HDR histogram
+/// with 3 significant digits.
+pub struct Latency {
+    hist: Histogram<u64>,
+}
+
+impl Default for Latency {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Latency {
+    #[inline]
+    pub fn new() -> Self {
+        let hist = Histogram::new_with_bounds(1, MAX_LATENCY_NANOS, 3).unwrap();
+        Latency{hist}
+    }
+
+    #[inline]
+    pub fn record(&mut self, dur: Duration) {
+        self.hist.saturating_record(dur.as_nanos() as u64)
+    }
+
+    /// Add latencies recorded by another one, e.g. of another thread.
+    #[inline]
+    pub fn merge(&mut self, other: &Latency) {
+        // both have same bounds
+        self.hist.add(&other.hist).unwrap()
+    }
+
+    /// Returns number of requests recorded.
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.hist.len()
+    }
+
+    /// Returns summary of recorded latencies.
+    #[inline]
+    pub fn report(&self) -> LatencyReport {
+        let at = |q: f64| Duration::from_nanos(self.hist.value_at_quantile(q));
+        LatencyReport{
+            min: Duration::from_nanos(self.hist.min()),
+            mean: Duration::from_nanos(self.hist.mean() as u64),
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            p999: at(0.999),
+            p9999: at(0.9999),
+            max: Duration::from_nanos(self.hist.max()),
+        }
+    }
+}
+
+/// Summary of latencies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencyReport {
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub p9999: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for LatencyReport {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "min is {:?}, mean is {:?}, p50 is {:?}, p90 is {:?}, p99 is {:?}, p99.9 is {:?}, p99.99 is {:?}, max is {:?}",
+            self.min, self.mean, self.p50, self.p90, self.p99, self.p999, self.p9999, self.max)
+    }
+}
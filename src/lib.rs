@@ -1,25 +1,82 @@
-use std::io::{self, Read, Write};
 use std::cell::UnsafeCell;
-use std::sync::atomic::{AtomicU8, Ordering};
-use std::mem::{self, align_of, MaybeUninit};
+use std::collections::VecDeque;
+use std::hint;
+use std::slice;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+use std::mem::{align_of, size_of, MaybeUninit};
+use std::time::{Duration, Instant};
 use libc::{
+    pthread_mutex_consistent,
+    pthread_mutex_destroy,
     pthread_mutex_init,
     pthread_mutex_lock,
     pthread_mutex_t,
     pthread_mutex_unlock,
     pthread_mutexattr_init,
     pthread_mutexattr_setpshared,
+    pthread_mutexattr_setrobust,
     pthread_mutexattr_t,
     pthread_condattr_init,
+    pthread_condattr_setclock,
     pthread_condattr_setpshared,
     pthread_condattr_t,
+    pthread_cond_destroy,
     pthread_cond_init,
     pthread_cond_signal,
+    pthread_cond_timedwait,
     pthread_cond_wait,
     pthread_cond_t,
+    pid_t,
+    timespec,
+    CLOCK_MONOTONIC,
+    EOWNERDEAD,
+    EPERM,
+    ETIMEDOUT,
+    PTHREAD_MUTEX_ROBUST,
     PTHREAD_PROCESS_SHARED,
 };
 use thiserror::Error;
+pub(crate) use futex::{futex_wait, futex_wake};
+use header::ShmHeader;
+
+mod affinity;
+mod baseline;
+mod byte_cabin;
+mod driver;
+mod epoll;
+mod futex;
+mod header;
+mod latency;
+mod numa;
+mod report;
+mod ring;
+mod shm;
+mod stats;
+mod stream;
+mod transport;
+mod uring;
+
+pub use affinity::{online_cpus, parse_cpu_list, pin_cpu};
+pub use baseline::{Baseline, Comparison, Delta};
+pub use byte_cabin::ByteCabin;
+pub use driver::{CabinClient, CabinServer, SessionStats};
+pub use epoll::{serve_epoll, EpollListener};
+pub use header::{SegmentKind, SHM_MAGIC, SHM_VERSION};
+pub use latency::{Latency, LatencyReport};
+pub use numa::{format_page_nodes, online_nodes};
+pub use report::{Field, HostInfo, OutputFormat, Printer, Record};
+pub use ring::{Ring, RingChannel, RingState, RING_CAPACITY};
+pub use shm::{ShmByteCabin, ShmCabin, ShmRing};
+pub use stats::Summary;
+pub use stream::{ShmStream, StreamChannel, STREAM_CAPACITY};
+pub use transport::{
+    Connection, Listener, RingClient, RingServer, ShmBytesTransport, ShmRingTransport,
+    ShmStreamListener, ShmStreamTransport, ShmTransport, StreamConn, TcpTransport, TcpUringTransport, Transport,
+    TransportOpts, UnixTransport, UnixUringTransport, UringListener, SHM_BYTES_CAPACITY,
+};
+pub use uring::UringConn;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -47,11 +104,95 @@ pub enum Error {
     FailPthreadWait(i32),
     #[error("fail to signal pthread_cond_t with code {0}")]
     FailPthreadSignal(i32),
+    #[error("no free cabin in pool")]
+    NoFreeCabin,
+    #[error("channel is busy")]
+    ChannelBusy,
+    #[error("unknown wait backend")]
+    UnknownWaitBackend,
+    #[error("fail to wait futex with code {0}")]
+    FailFutexWait(i32),
+    #[error("fail to wake futex with code {0}")]
+    FailFutexWake(i32),
+    #[error("owner of pthread_mutex_t is dead")]
+    OwnerDead,
+    #[error("client process is dead")]
+    ClientDead,
+    #[error("server process is dead")]
+    ServerDead,
+    #[error("shared memory is not initialized")]
+    NotInitialized,
+    #[error("bad magic number {0:#x} of shared memory")]
+    BadMagic(u64),
+    #[error("version of shared memory mismatch, expected {0}, found {1}")]
+    VersionMismatch(u32, u32),
+    #[error("kind of shared memory segment mismatch")]
+    SegmentKindMismatch,
+    #[error("size of request or response mismatch")]
+    SizeMismatch,
+    #[error("alignment of request or response mismatch")]
+    AlignMismatch,
+    #[error("layout hash of request or response mismatch")]
+    LayoutHashMismatch,
+    #[error("shared memory segment is smaller than its capacity")]
+    SegmentTooSmall,
+    #[error("payload of {0} bytes exceeds capacity of {1} bytes")]
+    PayloadTooLarge(usize, usize),
+    #[error("request or response is shorter than its header or payload")]
+    TruncatedMessage,
+    #[error("unknown output format")]
+    UnknownOutputFormat,
+    #[error("invalid duration, expected number with unit of ns, us, ms or s")]
+    InvalidDuration,
+    #[error("invalid size, expected number with optional unit of K, M or G")]
+    InvalidSize,
+    #[error("response does not match request in flight")]
+    UnexpectedResponse,
+    #[error("connection closed by peer")]
+    PeerClosed,
+    #[error("unknown arrival, expected constant or poisson")]
+    UnknownArrival,
+    #[error("unknown serve mode, expected serial, thread, pool:N, epoll or epoll:busy")]
+    UnknownServeMode,
+    #[error("invalid cpu list, expected comma separated numbers or ranges like 0-3")]
+    InvalidCpuList,
+    #[error("cpu {0} is not online")]
+    CpuNotOnline(usize),
+    #[error("fail to set cpu affinity with code {0}")]
+    FailSetAffinity(i32),
+    #[error("numa node {0} is not online")]
+    NodeNotOnline(usize),
+    #[error("fail to bind memory to numa node with code {0}")]
+    FailMbind(i32),
+    #[error("fail to query numa node of pages with code {0}")]
+    FailMovePages(i32),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Shmem(#[from] shared_memory::ShmemError),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[repr(u8)]
+/// Interval to check liveness of client when server is blocked.
+pub const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns whether process is still alive.
+/// Returns true if pid is 0, i.e. no process is recorded.
+#[inline]
+pub(crate) fn pid_alive(pid: pid_t) -> bool {
+    pid == 0 || unsafe { libc::kill(pid, 0) == 0 || errno::errno().0 == EPERM }
+}
+
+/// Returns pid of a process which already exited.
+#[cfg(test)]
+pub(crate) fn dead_pid() -> pid_t {
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    child.wait().unwrap();
+    child.id() as pid_t
+}
+
+#[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CabinState {
     AcceptingSpin = 0,
@@ -64,9 +205,9 @@ pub enum CabinState {
     Disconnected = 7,
 }
 
-impl From<u8> for CabinState {
+impl From<u32> for CabinState {
     #[inline]
-    fn from(src: u8) -> Self {
+    fn from(src: u32) -> Self {
         use CabinState::*;
         match src {
             0 => AcceptingSpin,
@@ -76,7 +217,39 @@ impl From<u8> for CabinState {
             4 => WaitReqYield,
             5 => WaitRespSpin,
             6 => WaitRespYield,
-            7 | _ => Disconnected,
+            _ => Disconnected,
+        }
+    }
+}
+
+/// Backend used to block and wake up peer in yield states.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitBackend {
+    /// Process-shared pthread mutex and condition variable.
+    Pthread = 0,
+    /// Shared futex on the state word of cabin.
+    Futex = 1,
+}
+
+impl From<u8> for WaitBackend {
+    #[inline]
+    fn from(src: u8) -> Self {
+        match src {
+            1 => WaitBackend::Futex,
+            _ => WaitBackend::Pthread,
+        }
+    }
+}
+
+impl FromStr for WaitBackend {
+    type Err = Error;
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pthread" => Ok(WaitBackend::Pthread),
+            "futex" => Ok(WaitBackend::Futex),
+            _ => Err(Error::UnknownWaitBackend),
         }
     }
 }
@@ -92,6 +265,10 @@ impl<'a, T, U> LockGuard<'a, T, U> {
         self.cabin.wait()
     }
 
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<()> {
+        self.cabin.wait_timeout(timeout)
+    }
+
     pub fn signal(&self) -> Result<()> {
         self.cabin.signal()
     }
@@ -103,23 +280,58 @@ impl<'a, T, U> Drop for LockGuard<'a, T, U> {
     }
 }
 
-#[repr(C)]
+/// Cabin is aligned to cache line so that cabins in one pool
+/// do not share cache lines with each other.
+#[repr(C, align(64))]
 pub struct Cabin<T, U> {
     mutex: UnsafeCell<pthread_mutex_t>,
     cond: UnsafeCell<pthread_cond_t>,
-    state: AtomicU8,
+    // 32-bit so that it can be used as futex word.
+    state: AtomicU32,
+    backend: u8,
+    claimed: AtomicBool,
     id: UnsafeCell<u32>,
+    client_pid: UnsafeCell<pid_t>,
+    server_pid: UnsafeCell<pid_t>,
     req: UnsafeCell<T>,
     resp: UnsafeCell<U>,
 }
 
+unsafe impl<T: Send, U: Send> Sync for Cabin<T, U> {}
+
 impl<T, U> Cabin<T, U> {
 
+    /// Returns memory size required to hold the cabin and its header.
+    #[inline]
+    pub fn mem_size() -> usize {
+        ShmHeader::mem_size::<Self>(size_of::<Self>())
+    }
+
+    /// Initialize a cabin with header in given memory, client follows the
+    /// wait backend chosen here.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to zeroed shared memory of at least
+    /// [`Cabin::mem_size`] bytes, and must outlive the guard.
+    #[inline]
+    pub unsafe fn new(mem: *mut u8, backend: WaitBackend, _guard: &CabinGuard) -> Result<&Self> {
+        let (header, ptr) = ShmHeader::init::<T, U, Self>(mem, SegmentKind::Cabin);
+        let cabin = Self::init(ptr, backend)?;
+        header.mark_initialized();
+        Ok(cabin)
+    }
+
+    /// Initialize a cabin in place without header.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned and point to zeroed shared memory of cabin size.
     #[inline]
-    pub unsafe fn new<'a>(mem: *mut u8, _guard: &'a CabinGuard) -> Result<&'a Self> {
-        let padding = mem.align_offset(align_of::<Self>());
-        let ptr = mem.add(padding);
-        let cabin = mem::transmute::<*mut u8, &mut Self>(ptr);
+    pub(crate) unsafe fn init<'a>(ptr: *mut u8, backend: WaitBackend) -> Result<&'a Self> {
+        let cabin = &mut *(ptr as *mut Self);
+        cabin.backend = backend as u8;
+        cabin.server_pid = UnsafeCell::new(std::process::id() as pid_t);
         // initialize pthread mutex
         let mut lock_attr: pthread_mutexattr_t = MaybeUninit::zeroed().assume_init();
         if pthread_mutexattr_init(&mut lock_attr) != 0 {
@@ -128,6 +340,10 @@ impl<T, U> Cabin<T, U> {
         if pthread_mutexattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED) != 0 {
             return Err(Error::FailSetupPthreadMutexAttr)
         }
+        // robust mutex, so that lock does not block forever if owner is dead
+        if pthread_mutexattr_setrobust(&mut lock_attr, PTHREAD_MUTEX_ROBUST) != 0 {
+            return Err(Error::FailSetupPthreadMutexAttr)
+        }
         if pthread_mutex_init(cabin.mutex.get(), &lock_attr) != 0 {
             return Err(Error::FailInitPthreadMutex)
         }
@@ -139,17 +355,100 @@ impl<T, U> Cabin<T, U> {
         if pthread_condattr_setpshared(&mut cond_attr, PTHREAD_PROCESS_SHARED) != 0 {
             return Err(Error::FailSetupPthreadCondAttr)
         }
+        if pthread_condattr_setclock(&mut cond_attr, CLOCK_MONOTONIC) != 0 {
+            return Err(Error::FailSetupPthreadCondAttr)
+        }
         if pthread_cond_init(cabin.cond.get(), &cond_attr) != 0 {
             return Err(Error::FailInitPthreadCond)
         }
         Ok(cabin)
     }
 
+    /// Destroy pthread mutex and cond of the cabin.
+    ///
+    /// # Safety
+    ///
+    /// No process may use the cabin afterwards.
+    #[inline]
+    pub(crate) unsafe fn destroy(&self) {
+        pthread_cond_destroy(self.cond.get());
+        pthread_mutex_destroy(self.mutex.get());
+    }
+
+    /// Attach to a cabin already initialized by another process.
+    /// Header is validated so that mismatched `T` and `U` or stale
+    /// segment are rejected.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to shared memory of at least header size,
+    /// and must outlive the guard.
+    #[inline]
+    pub unsafe fn from_existing(mem: *mut u8, _guard: &CabinGuard) -> Result<&Self> {
+        let (_, ptr) = ShmHeader::attach::<T, U, Self>(mem, SegmentKind::Cabin)?;
+        Ok(&*(ptr as *const Self))
+    }
+
+    /// Try to claim this cabin exclusively, returns true if succeeds.
+    /// Client must claim the cabin before connecting.
+    #[inline]
+    pub fn try_claim(&self) -> bool {
+        self.claimed.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    }
+
+    /// Release the claim so that another client can use this cabin.
+    /// Server releases the cabin after it's reset to accepting state.
+    #[inline]
+    pub fn release(&self) {
+        self.claimed.store(false, Ordering::Release)
+    }
+
+    /// Reset the cabin to accepting state and release it.
+    /// Server calls it after client disconnected or died.
+    #[inline]
+    pub fn reset(&self) {
+        self.set_client_pid(0);
+        self.state.store(CabinState::AcceptingSpin as u32, Ordering::SeqCst);
+        self.release();
+    }
+
+    #[inline]
+    pub fn client_pid(&self) -> pid_t {
+        unsafe { self.client_pid.get().read_volatile() }
+    }
+
+    #[inline]
+    pub fn set_client_pid(&self, pid: pid_t) {
+        unsafe { self.client_pid.get().write_volatile(pid) }
+    }
+
+    /// Returns whether client process is still alive.
+    /// Returns true if no client process is recorded.
+    #[inline]
+    pub fn client_alive(&self) -> bool {
+        pid_alive(self.client_pid())
+    }
+
+    #[inline]
+    pub fn server_pid(&self) -> pid_t {
+        unsafe { self.server_pid.get().read_volatile() }
+    }
+
+    #[inline]
+    pub fn set_server_pid(&self, pid: pid_t) {
+        unsafe { self.server_pid.get().write_volatile(pid) }
+    }
+
+    /// Returns whether server process is still alive.
+    /// Returns true if no server process is recorded.
+    #[inline]
+    pub fn server_alive(&self) -> bool {
+        pid_alive(self.server_pid())
+    }
+
     #[inline]
-    pub unsafe fn from_existing<'a>(mem: *mut u8, _guard: &'a CabinGuard) -> &'a Self {
-        let padding = mem.align_offset(align_of::<Self>());
-        let ptr = mem.add(padding);
-        mem::transmute::<*mut u8, &Self>(ptr)
+    pub fn backend(&self) -> WaitBackend {
+        self.backend.into()
     }
 
     pub fn id(&self) -> u32 {
@@ -183,20 +482,96 @@ impl<T, U> Cabin<T, U> {
 
     #[inline]
     pub fn cas_state(&self, current: CabinState, new: CabinState) -> std::result::Result<CabinState, CabinState> {
-        self.state.compare_exchange_weak(current as u8, new as u8, Ordering::SeqCst, Ordering::SeqCst)
+        self.state.compare_exchange_weak(current as u32, new as u32, Ordering::SeqCst, Ordering::SeqCst)
             .map(|s| s.into())
             .map_err(|s| s.into())
     }
 
+    /// Block current thread until state is no longer given yield state.
+    /// Liveness of peer is checked periodically, returns [`Error::ClientDead`]
+    /// or [`Error::ServerDead`] if peer process died while waiting.
+    #[inline]
+    pub fn wait_while(&self, state: CabinState) -> Result<()> {
+        match self.backend() {
+            WaitBackend::Pthread => {
+                let lg = self.lock()?;
+                while self.load_state(Ordering::Acquire) == state {
+                    lg.wait_timeout(LIVENESS_CHECK_INTERVAL)?;
+                    if self.load_state(Ordering::Acquire) == state {
+                        self.check_peer(state)?;
+                    }
+                }
+            }
+            WaitBackend::Futex => {
+                while self.load_state(Ordering::Acquire) == state {
+                    futex_wait(&self.state, state as u32, LIVENESS_CHECK_INTERVAL)?;
+                    if self.load_state(Ordering::Acquire) == state {
+                        self.check_peer(state)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check liveness of peer of the waiter in given state, client waits
+    /// for response and server waits for anything else.
+    #[inline]
+    fn check_peer(&self, state: CabinState) -> Result<()> {
+        match state {
+            CabinState::WaitRespYield if !self.server_alive() => Err(Error::ServerDead),
+            CabinState::WaitRespYield => Ok(()),
+            _ if !self.client_alive() => Err(Error::ClientDead),
+            _ => Ok(()),
+        }
+    }
+
+    /// Transfer state from yield state and wake up peer blocked in
+    /// [`Cabin::wait_while`].
+    /// Only the waker can change yield state, so current state must match.
+    #[inline]
+    pub fn switch_and_wake(&self, current: CabinState, new: CabinState) -> Result<()> {
+        match self.backend() {
+            WaitBackend::Pthread => {
+                let lg = self.lock()?;
+                self.state.compare_exchange(current as u32, new as u32, Ordering::SeqCst, Ordering::SeqCst)
+                    .map_err(|_| Error::UnknownState)?;
+                lg.signal()
+            }
+            WaitBackend::Futex => {
+                self.state.compare_exchange(current as u32, new as u32, Ordering::SeqCst, Ordering::SeqCst)
+                    .map_err(|_| Error::UnknownState)?;
+                futex_wake(&self.state)
+            }
+        }
+    }
+
+    /// Lock the mutex, returns [`Error::OwnerDead`] if previous owner died
+    /// with the lock held. In such case the mutex is made consistent and
+    /// unlocked, the caller should reset the cabin.
     #[inline]
     pub fn lock(&self) -> Result<LockGuard<'_, T, U>> {
         let res = unsafe { pthread_mutex_lock(self.mutex.get()) };
+        if res == EOWNERDEAD {
+            self.make_consistent()?;
+            self.unlock()?;
+            return Err(Error::OwnerDead)
+        }
         if res != 0 {
             return Err(Error::FailPthreadLock(res))
         }
         Ok(LockGuard{cabin: self})
     }
 
+    #[inline]
+    fn make_consistent(&self) -> Result<()> {
+        let res = unsafe { pthread_mutex_consistent(self.mutex.get()) };
+        if res != 0 {
+            return Err(Error::FailPthreadLock(res))
+        }
+        Ok(())
+    }
+
     #[inline]
     fn unlock(&self) -> Result<()> {
         let res = unsafe { pthread_mutex_unlock(self.mutex.get()) };
@@ -209,12 +584,36 @@ impl<T, U> Cabin<T, U> {
     #[inline]
     fn wait(&self) -> Result<()> {
         let res = unsafe { pthread_cond_wait(self.cond.get(), self.mutex.get()) };
+        if res == EOWNERDEAD {
+            // mutex is re-acquired, lock guard will unlock it
+            self.make_consistent()?;
+            return Err(Error::OwnerDead)
+        }
         if res != 0 {
             return Err(Error::FailPthreadWait(res))
         }
         Ok(())
     }
 
+    #[inline]
+    fn wait_timeout(&self, timeout: Duration) -> Result<()> {
+        let mut ts: timespec = unsafe { MaybeUninit::zeroed().assume_init() };
+        unsafe { libc::clock_gettime(CLOCK_MONOTONIC, &mut ts); }
+        let nsec = ts.tv_nsec as u64 + timeout.subsec_nanos() as u64;
+        ts.tv_sec += (timeout.as_secs() + nsec / 1_000_000_000) as libc::time_t;
+        ts.tv_nsec = (nsec % 1_000_000_000) as _;
+        let res = unsafe { pthread_cond_timedwait(self.cond.get(), self.mutex.get(), &ts) };
+        if res == EOWNERDEAD {
+            // mutex is re-acquired, lock guard will unlock it
+            self.make_consistent()?;
+            return Err(Error::OwnerDead)
+        }
+        if res != 0 && res != ETIMEDOUT {
+            return Err(Error::FailPthreadWait(res))
+        }
+        Ok(())
+    }
+
     #[inline]
     fn signal(&self) -> Result<()> {
         let res = unsafe { pthread_cond_signal(self.cond.get()) };
@@ -225,89 +624,647 @@ impl<T, U> Cabin<T, U> {
     }
 }
 
-#[derive(Debug)]
+/// CabinPool hosts multiple cabins in one shared memory segment,
+/// so that multiple clients can connect concurrently.
+/// Cabins are laid out consecutively right after the pool.
+#[repr(C)]
+pub struct CabinPool<T, U> {
+    slots: usize,
+    // align pool same as cabins
+    _cabins: [Cabin<T, U>; 0],
+}
+
+impl<T, U> CabinPool<T, U> {
+    /// Returns memory size required to hold pool with given slots.
+    #[inline]
+    pub fn mem_size(slots: usize) -> usize {
+        ShmHeader::mem_size::<Self>(Self::cabin_offset() + size_of::<Cabin<T, U>>() * slots)
+    }
+
+    #[inline]
+    fn cabin_offset() -> usize {
+        let align = align_of::<Cabin<T, U>>();
+        size_of::<Self>().next_multiple_of(align)
+    }
+
+    /// Initialize a pool of cabins in given memory.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to zeroed shared memory of at least
+    /// [`CabinPool::mem_size`] bytes, and must outlive the guard.
+    #[inline]
+    pub unsafe fn new(mem: *mut u8, slots: usize, backend: WaitBackend, _guard: &CabinGuard) -> Result<&Self> {
+        let (header, ptr) = ShmHeader::init::<T, U, Self>(mem, SegmentKind::CabinPool);
+        let pool = &mut *(ptr as *mut Self);
+        pool.slots = slots;
+        let cabin_ptr = ptr.add(Self::cabin_offset());
+        for i in 0..slots {
+            Cabin::<T, U>::init(cabin_ptr.add(size_of::<Cabin<T, U>>() * i), backend)?;
+        }
+        header.mark_initialized();
+        Ok(pool)
+    }
+
+    /// Attach to a pool already initialized by another process.
+    /// Header is validated so that mismatched `T` and `U` or stale
+    /// segment are rejected.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to shared memory of at least header size,
+    /// and must outlive the guard.
+    #[inline]
+    pub unsafe fn from_existing(mem: *mut u8, _guard: &CabinGuard) -> Result<&Self> {
+        let (_, ptr) = ShmHeader::attach::<T, U, Self>(mem, SegmentKind::CabinPool)?;
+        Ok(&*(ptr as *const Self))
+    }
+
+    #[inline]
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    #[inline]
+    pub fn cabins(&self) -> &[Cabin<T, U>] {
+        unsafe {
+            let ptr = (self as *const Self as *const u8).add(Self::cabin_offset());
+            slice::from_raw_parts(ptr as *const Cabin<T, U>, self.slots)
+        }
+    }
+
+    /// Claim a free cabin in the pool, returns slot number and the cabin.
+    /// Pid of current process is recorded so server can detect its death.
+    #[inline]
+    pub fn claim(&self) -> Result<(usize, &Cabin<T, U>)> {
+        let (slot, cabin) = self.cabins().iter()
+            .enumerate()
+            .find(|(_, cabin)| cabin.try_claim())
+            .ok_or(Error::NoFreeCabin)?;
+        cabin.set_client_pid(std::process::id() as pid_t);
+        Ok((slot, cabin))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnKind {
     Tcp,
     Unix,
+    /// TCP driven by io_uring.
+    TcpUring,
+    /// Unix socket driven by io_uring.
+    UnixUring,
     Shm,
+    /// Shared memory cabin with byte buffers, carries payload in one hand-off.
+    ShmBytes,
+    ShmRing,
+    ShmStream,
+}
+
+impl ConnKind {
+    pub const ALL: [ConnKind; 8] = [
+        ConnKind::Tcp,
+        ConnKind::Unix,
+        ConnKind::TcpUring,
+        ConnKind::UnixUring,
+        ConnKind::Shm,
+        ConnKind::ShmBytes,
+        ConnKind::ShmRing,
+        ConnKind::ShmStream,
+    ];
+
+    /// Returns scheme of address, e.g. "tcp" in "tcp:127.0.0.1:9001".
+    #[inline]
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            ConnKind::Tcp => "tcp",
+            ConnKind::Unix => "unix",
+            ConnKind::TcpUring => "tcp-uring",
+            ConnKind::UnixUring => "unix-uring",
+            ConnKind::Shm => "shm",
+            ConnKind::ShmBytes => "shm-bytes",
+            ConnKind::ShmRing => "shm-ring",
+            ConnKind::ShmStream => "shm-stream",
+        }
+    }
 }
 
 #[inline]
 pub fn parse_conn_kind(s: &str) -> Result<(ConnKind, String)> {
-    for (proto, kind) in [
-        ("tcp", ConnKind::Tcp), 
-        ("unix", ConnKind::Unix), 
-        ("shm", ConnKind::Shm),
-    ] {
-        if s.starts_with(proto) {
-            return Ok((kind, s[proto.len()+1..].to_string()))
+    for kind in ConnKind::ALL {
+        if let Some(addr) = s.strip_prefix(kind.scheme()).and_then(|s| s.strip_prefix(':')) {
+            return Ok((kind, addr.to_string()))
         }
     }
     Err(Error::UnknownProtocol)
 }
 
+/// Warmup runs before measurement with the same protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Warmup {
+    #[default]
+    None,
+    /// Send given number of requests.
+    Count(u32),
+    /// Keep sending requests for given duration.
+    Duration(Duration),
+}
+
+impl FromStr for Warmup {
+    type Err = Error;
+    /// Parse number of requests, or duration with unit, e.g. "500ms".
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(count) = s.parse::<u32>() {
+            if count == 0 {
+                return Ok(Warmup::None)
+            }
+            return Ok(Warmup::Count(count))
+        }
+        parse_duration(s).map(Warmup::Duration)
+    }
+}
+
+impl std::fmt::Display for Warmup {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warmup::None => f.write_str("none"),
+            Warmup::Count(count) => write!(f, "{}", count),
+            Warmup::Duration(dur) => write!(f, "{:?}", dur),
+        }
+    }
+}
+
+/// Parse duration with unit of ns, us, ms or s, e.g. "500ms".
+#[inline]
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (num, nanos) = [("ns", 1), ("us", 1_000), ("ms", 1_000_000), ("s", 1_000_000_000)]
+        .into_iter()
+        .find_map(|(unit, nanos)| s.strip_suffix(unit).map(|num| (num, nanos)))
+        .ok_or(Error::InvalidDuration)?;
+    let num: f64 = num.trim().parse().map_err(|_| Error::InvalidDuration)?;
+    if !num.is_finite() || num < 0.0 {
+        return Err(Error::InvalidDuration)
+    }
+    Ok(Duration::from_nanos((num * nanos as f64) as u64))
+}
+
+/// Parse size in bytes with optional unit of K, M or G, e.g. "4K".
+#[inline]
+pub fn parse_size(s: &str) -> Result<usize> {
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let num: usize = num.trim().parse().map_err(|_| Error::InvalidSize)?;
+    num.checked_mul(unit).ok_or(Error::InvalidSize)
+}
+
+/// Parse comma separated sizes, each item is a size, or a range
+/// "from..to" expanded in powers of two, e.g. "8..4M".
+/// Sizes must not exceed [`MAX_PAYLOAD`].
+#[inline]
+pub fn parse_sizes(s: &str) -> Result<Vec<usize>> {
+    let mut sizes = vec![];
+    for item in s.split(',') {
+        if let Some((from, to)) = item.split_once("..") {
+            let (mut size, to) = (parse_size(from)?, parse_size(to)?);
+            if size == 0 || size > to {
+                return Err(Error::InvalidSize)
+            }
+            while size <= to {
+                sizes.push(size);
+                size = size.checked_mul(2).ok_or(Error::InvalidSize)?;
+            }
+        } else {
+            sizes.push(parse_size(item)?);
+        }
+    }
+    if let Some(&size) = sizes.iter().find(|&&size| size > MAX_PAYLOAD) {
+        return Err(Error::PayloadTooLarge(size, MAX_PAYLOAD))
+    }
+    Ok(sizes)
+}
+
+/// Max number of requests between checks of elapsed time in warmup.
+const WARMUP_BATCH: u32 = 1024;
+
+/// Arrival process of requests in open loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    /// Requests are evenly spaced.
+    Constant,
+    /// Intervals between requests are exponentially distributed.
+    Poisson,
+}
+
+impl FromStr for Arrival {
+    type Err = Error;
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "constant" => Ok(Arrival::Constant),
+            "poisson" => Ok(Arrival::Poisson),
+            _ => Err(Error::UnknownArrival),
+        }
+    }
+}
+
+/// Target rate of requests in open loop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// Requests per second.
+    pub per_sec: f64,
+    pub arrival: Arrival,
+}
+
+impl Rate {
+    /// Returns interval until next request.
+    #[inline]
+    fn interval(&self) -> Duration {
+        let secs = match self.arrival {
+            Arrival::Constant => 1.0 / self.per_sec,
+            // inverse transform sampling, 1-u is in (0, 1]
+            Arrival::Poisson => -(1.0 - rand::random::<f64>()).ln() / self.per_sec,
+        };
+        Duration::from_secs_f64(secs)
+    }
+}
+
+impl std::fmt::Display for Rate {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arrival = match self.arrival {
+            Arrival::Constant => "constant",
+            Arrival::Poisson => "poisson",
+        };
+        write!(f, "{}/s {}", self.per_sec, arrival)
+    }
+}
+
+/// Workload sent by client over a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    /// Closed loop as [`client_conn`], send the value or 0 to num-1 if
+    /// not specified.
+    Value(Option<u64>),
+    /// Keep given number of requests in flight as [`client_window`].
+    Window(usize),
+    /// Send payload of given size as [`client_payload`].
+    Payload(usize),
+    /// Open loop at target rate as [`client_rate`].
+    Rate(Rate),
+}
+
+impl Workload {
+    #[inline]
+    fn run<C: Connection, F: FnMut(Duration)>(&self, conn: C, num: u32, record: F) -> Result<u64> {
+        match *self {
+            Workload::Value(value) => client_loop(conn, value, num, record),
+            Workload::Window(window) => window_loop(conn, window, num, record),
+            Workload::Payload(size) => payload_loop(conn, size, num, record),
+            Workload::Rate(rate) => rate_loop(conn, rate, num, record),
+        }
+    }
+
+    /// Returns mean interval between requests of open loop, zero for
+    /// closed loop.
+    #[inline]
+    fn mean_interval(&self) -> Duration {
+        match *self {
+            Workload::Rate(rate) => Duration::from_secs_f64(1.0 / rate.per_sec),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Run warmup over the connection, returns sum of values sent.
+/// Requests are sent as the workload does, but not timed.
+#[inline]
+pub fn warmup_conn<C: Connection>(mut conn: C, workload: Workload, warmup: Warmup) -> Result<u64> {
+    match warmup {
+        Warmup::None => Ok(0),
+        Warmup::Count(count) => workload.run(&mut conn, count, |_| ()),
+        Warmup::Duration(dur) => {
+            let inst = Instant::now();
+            let mut sum = 0;
+            // first batch of one request estimates time of each request
+            let mut batch = 1;
+            loop {
+                let batch_inst = Instant::now();
+                sum += workload.run(&mut conn, batch, |_| ())?;
+                let elapsed = inst.elapsed();
+                if elapsed >= dur {
+                    return Ok(sum)
+                }
+                // limit batch to the time remaining, so slow requests of
+                // open loop or large payload do not overrun the warmup
+                let per_req = (batch_inst.elapsed() / batch).max(workload.mean_interval()).as_nanos().max(1);
+                batch = ((dur - elapsed).as_nanos() / per_req).clamp(1, WARMUP_BATCH as u128) as u32;
+            }
+        }
+    }
+}
+
+/// Send `num` requests of the workload, returns sum of values sent.
+#[inline]
+pub fn client_workload<C: Connection>(conn: C, workload: Workload, num: u32, latency: &mut Latency) -> Result<u64> {
+    workload.run(conn, num, |dur| latency.record(dur))
+}
+
+/// Send `num` requests keeping at most `window` of them in flight,
+/// returns sum of values sent.
+/// Request with sequence number `seq` carries odd value `2*seq+1`, so
+/// server responds to each. Responses are matched in FIFO order, and
+/// latency of each request is timed from sending until its response.
+/// The window is limited by [`Connection::max_in_flight`].
+#[inline]
+pub fn client_window<C: Connection>(conn: C, window: usize, num: u32, latency: &mut Latency) -> Result<u64> {
+    window_loop(conn, window, num, |dur| latency.record(dur))
+}
+
+#[inline]
+fn window_loop<C: Connection, F: FnMut(Duration)>(mut conn: C, window: usize, num: u32, mut record: F) -> Result<u64> {
+    let window = window.clamp(1, conn.max_in_flight());
+    // send time and value of requests in flight
+    let mut in_flight = VecDeque::with_capacity(window);
+    let mut last_resp: Option<u64> = None;
+    let mut recv_one = |conn: &mut C, in_flight: &mut VecDeque<(Instant, u64)>| -> Result<()> {
+        let resp = conn.recv()?.ok_or(Error::PeerClosed)?;
+        let (inst, value) = in_flight.pop_front().unwrap();
+        record(inst.elapsed());
+        // server responds with sum of all values, so consecutive responses
+        // differ by value of the request
+        if let Some(last) = last_resp {
+            if resp.wrapping_sub(last) != value {
+                return Err(Error::UnexpectedResponse)
+            }
+        }
+        last_resp = Some(resp);
+        Ok(())
+    };
+    let mut sum = 0u64;
+    for seq in 0..num as u64 {
+        if in_flight.len() == window {
+            recv_one(&mut conn, &mut in_flight)?;
+        }
+        let value = 2 * seq + 1;
+        let inst = Instant::now();
+        // send request
+        conn.send(value)?;
+        sum += value;
+        in_flight.push_back((inst, value));
+    }
+    while !in_flight.is_empty() {
+        recv_one(&mut conn, &mut in_flight)?;
+    }
+    Ok(sum)
+}
+
+/// Waiting shorter than this spins instead of sleeping, as sleep may
+/// overshoot by tens of microseconds.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(100);
+
+/// Send `num` requests at target rate regardless of how long previous
+/// responses take, returns sum of values sent.
+/// Request with sequence number `seq` carries odd value `2*seq+1`, so
+/// server responds to each. Latency is timed from the intended send time
+/// of the request instead of actual one, so the delay caused by slow
+/// response is counted for following requests, which corrects
+/// coordinated omission of closed loop.
+#[inline]
+pub fn client_rate<C: Connection>(conn: C, rate: Rate, num: u32, latency: &mut Latency) -> Result<u64> {
+    rate_loop(conn, rate, num, |dur| latency.record(dur))
+}
+
 #[inline]
-pub fn client_conn<T>(mut conn: T, value: Option<u64>, num: u32) -> anyhow::Result<u64> 
-where
-    T: Read + Write,
-{
+fn rate_loop<C: Connection, F: FnMut(Duration)>(mut conn: C, rate: Rate, num: u32, mut record: F) -> Result<u64> {
+    let mut sum = 0u64;
+    let mut intended = Instant::now();
+    for seq in 0..num as u64 {
+        // wait until intended send time, late request is sent at once
+        loop {
+            let now = Instant::now();
+            if now >= intended {
+                break
+            }
+            let remaining = intended - now;
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep(remaining - SPIN_THRESHOLD);
+            } else {
+                hint::spin_loop();
+            }
+        }
+        let value = 2 * seq + 1;
+        // send request
+        conn.send(value)?;
+        sum += value;
+        conn.recv()?.ok_or(Error::PeerClosed)?;
+        record(intended.elapsed());
+        intended += rate.interval();
+    }
+    Ok(sum)
+}
+
+/// Flag of header of payload frame, lower bits are length of payload.
+pub const PAYLOAD_FLAG: u64 = 1 << 63;
+
+/// Largest payload accepted by server and by `--sizes`, so that a bad
+/// frame header does not make server allocate unbounded memory.
+pub const MAX_PAYLOAD: usize = 1 << 30;
+
+/// Send `num` payload frames of given size, returns total bytes sent.
+/// Server always responds to payload frame, and latency of each
+/// request is timed from sending until its response.
+#[inline]
+pub fn client_payload<C: Connection>(conn: C, size: usize, num: u32, latency: &mut Latency) -> Result<u64> {
+    payload_loop(conn, size, num, |dur| latency.record(dur))
+}
+
+#[inline]
+fn payload_loop<C: Connection, F: FnMut(Duration)>(mut conn: C, size: usize, num: u32, mut record: F) -> Result<u64> {
+    let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+    let mut sum = 0u64;
+    for _ in 0..num {
+        let inst = Instant::now();
+        // send request
+        conn.send_frame(&payload)?;
+        sum += size as u64;
+        conn.recv()?.ok_or(Error::PeerClosed)?;
+        record(inst.elapsed());
+    }
+    Ok(sum)
+}
+
+/// Send `num` requests to server and returns sum of values sent.
+/// Response is read only if value is odd.
+/// If value is not specified, send 0 to num-1.
+/// Each request is timed individually, including reading its response.
+#[inline]
+pub fn client_conn<C: Connection>(conn: C, value: Option<u64>, num: u32, latency: &mut Latency) -> Result<u64> {
+    client_loop(conn, value, num, |dur| latency.record(dur))
+}
+
+#[inline]
+fn client_loop<C: Connection, F: FnMut(Duration)>(mut conn: C, value: Option<u64>, num: u32, mut record: F) -> Result<u64> {
     let mut sum = 0u64;
     if let Some(value) = value {
         if value & 1 == 1 {
             // read response only if value is odd
             for _ in 0..num {
-                let mut buf = value.to_le_bytes();
+                let inst = Instant::now();
                 // send request
-                conn.write_all(&buf)?;
-                conn.flush()?;
+                conn.send(value)?;
                 sum += value;
-                conn.read_exact(&mut buf)?;
+                conn.recv()?.ok_or(Error::PeerClosed)?;
+                record(inst.elapsed());
             }
         } else {
             for _ in 0..num {
-                let buf = value.to_le_bytes();
+                let inst = Instant::now();
                 // send request
-                conn.write_all(&buf)?;
-                conn.flush()?;
+                conn.send(value)?;
                 sum += value;
+                conn.flush()?;
+                record(inst.elapsed());
             }
         }
     } else {
         for value in 0..num as u64 {
-            let mut buf = value.to_le_bytes();
+            let inst = Instant::now();
             // send request
-            conn.write_all(&buf)?;
-            conn.flush()?;
+            conn.send(value)?;
             sum += value;
             if value & 1 == 1 {
                 // only read response if value is odd
-                conn.read_exact(&mut buf)?;
-                // debug_assert_eq!(sum, u64::from_le_bytes(buf));
+                let _resp = conn.recv()?.ok_or(Error::PeerClosed)?;
+                // debug_assert_eq!(sum, _resp);
+            } else {
+                conn.flush()?;
             }
+            record(inst.elapsed());
         }
     }
     Ok(sum)
 }
 
+/// How server handles connections of socket transports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServeMode {
+    /// Handle connections one by one on the accepting thread.
+    Serial,
+    /// Spawn a thread for each connection.
+    Thread,
+    /// Hand connections to a fixed number of worker threads.
+    Pool(usize),
+    /// Handle all connections on one thread with epoll, busy poll
+    /// without blocking in `epoll_wait` if set.
+    Epoll { busy_poll: bool },
+}
+
+impl FromStr for ServeMode {
+    type Err = Error;
+    /// Parse "serial", "thread", "pool:N", "epoll" or "epoll:busy".
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "serial" => Ok(ServeMode::Serial),
+            "thread" => Ok(ServeMode::Thread),
+            "epoll" => Ok(ServeMode::Epoll{busy_poll: false}),
+            "epoll:busy" => Ok(ServeMode::Epoll{busy_poll: true}),
+            _ => match s.strip_prefix("pool:").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(ServeMode::Pool(n)),
+                _ => Err(Error::UnknownServeMode),
+            }
+        }
+    }
+}
 
-#[inline]
-pub fn server_conn<T>(mut conn: T) -> anyhow::Result<u64> 
-where
-    T: Read + Write,
-{
-    // read 8 bytes as little-endian integer and sum.
-    let mut sum = 0u64;
-    let mut buf = [0u8; 8];
-    // read request
-    while conn.read_exact(&mut buf).is_ok() {
-        let value = u64::from_le_bytes(buf);
-        sum += value;
-        
+impl std::fmt::Display for ServeMode {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServeMode::Serial => f.write_str("serial"),
+            ServeMode::Thread => f.write_str("thread"),
+            ServeMode::Pool(n) => write!(f, "pool:{}", n),
+            ServeMode::Epoll{busy_poll: false} => f.write_str("epoll"),
+            ServeMode::Epoll{busy_poll: true} => f.write_str("epoll:busy"),
+        }
+    }
+}
+
+/// Action of server after receiving a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerAction {
+    /// No response.
+    None,
+    /// Respond with the value.
+    Reply(u64),
+    /// Payload of given length follows, respond after it is received.
+    Payload(usize),
+}
+
+/// Server side of the protocol, shared by blocking and event driven servers.
+/// Values received are summed, and current sum is replied to request with
+/// odd value. Length of payload frame is summed, and the frame is always
+/// replied.
+#[derive(Debug, Default)]
+pub struct ServerState {
+    sum: u64,
+}
+
+impl ServerState {
+    #[inline]
+    pub fn on_value(&mut self, value: u64) -> ServerAction {
+        if value & PAYLOAD_FLAG != 0 {
+            return ServerAction::Payload((value & !PAYLOAD_FLAG) as usize)
+        }
+        self.sum += value;
         if value & 1 == 1 {
             // only send response if value is odd
-            conn.write_all(&sum.to_le_bytes())?;
-            conn.flush()?;
+            ServerAction::Reply(self.sum)
+        } else {
+            ServerAction::None
         }
     }
-    Ok(sum)
+
+    /// Payload of given length is received, returns the response.
+    #[inline]
+    pub fn on_payload(&mut self, len: usize) -> u64 {
+        self.sum += len as u64;
+        self.sum
+    }
+
+    #[inline]
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+}
+
+/// Serve the connection with [`ServerState`] until client disconnects,
+/// returns sum of values received.
+/// Payload longer than [`MAX_PAYLOAD`] fails with [`Error::PayloadTooLarge`].
+#[inline]
+pub fn server_conn<C: Connection>(mut conn: C) -> Result<u64> {
+    let mut state = ServerState::default();
+    let mut payload = Vec::new();
+    // read request
+    while let Some(value) = conn.recv()? {
+        match state.on_value(value) {
+            ServerAction::None => (),
+            ServerAction::Reply(resp) => conn.send(resp)?,
+            ServerAction::Payload(len) => {
+                if len > MAX_PAYLOAD {
+                    return Err(Error::PayloadTooLarge(len, MAX_PAYLOAD))
+                }
+                payload.resize(len, 0);
+                conn.recv_payload(&mut payload)?;
+                conn.send(state.on_payload(len))?;
+            }
+        }
+    }
+    Ok(state.sum())
 }
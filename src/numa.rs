@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::ptr;
+use crate::{parse_cpu_list, Error, Result};
+
+/// File listing NUMA nodes currently online, in same list format as CPUs.
+const NODE_ONLINE_PATH: &str = "/sys/devices/system/node/online";
+
+/// Max number of nodes can be bound.
+const MAX_NODES: usize = 1024;
+
+const MPOL_BIND: libc::c_int = 2;
+const MPOL_MF_STRICT: libc::c_uint = 1 << 0;
+const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+
+/// Returns NUMA nodes currently online, kernel without NUMA support
+/// has only node 0.
+#[inline]
+pub fn online_nodes() -> Result<Vec<usize>> {
+    match fs::read_to_string(NODE_ONLINE_PATH) {
+        Ok(s) => parse_cpu_list(&s),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![0]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[inline]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Bind pages of the memory to given node with `mbind`, pages already
+/// touched are moved.
+///
+/// # Safety
+///
+/// `mem` must be page aligned start of a mapping of at least `len` bytes.
+#[inline]
+pub unsafe fn bind_node(mem: *mut u8, len: usize, node: usize) -> Result<()> {
+    if node >= MAX_NODES || !online_nodes()?.contains(&node) {
+        return Err(Error::NodeNotOnline(node))
+    }
+    let bits = libc::c_ulong::BITS as usize;
+    let mut mask = [0 as libc::c_ulong; MAX_NODES / libc::c_ulong::BITS as usize];
+    mask[node / bits] |= 1 << (node % bits);
+    // kernel ignores the last bit of maxnode
+    let maxnode = MAX_NODES + 1;
+    let res = libc::syscall(libc::SYS_mbind, mem, len, MPOL_BIND, mask.as_ptr(), maxnode, MPOL_MF_STRICT | MPOL_MF_MOVE);
+    if res != 0 {
+        return Err(Error::FailMbind(errno::errno().0))
+    }
+    Ok(())
+}
+
+/// Touch every page of the memory so that pages are allocated according
+/// to memory policy before benchmark.
+///
+/// # Safety
+///
+/// `mem` must be valid for `len` bytes, and no one else accesses it.
+#[inline]
+pub unsafe fn prefault(mem: *mut u8, len: usize) {
+    for offset in (0..len).step_by(page_size()) {
+        let p = mem.add(offset);
+        ptr::write_volatile(p, ptr::read_volatile(p));
+    }
+}
+
+/// Returns node of each page of the memory, queried by `move_pages`
+/// without moving. Negative value is error code of the page, e.g.
+/// `-ENOENT` if the page is not present.
+///
+/// # Safety
+///
+/// `mem` must be page aligned start of a mapping of at least `len` bytes.
+#[inline]
+pub unsafe fn page_nodes(mem: *const u8, len: usize) -> Result<Vec<i32>> {
+    let pages: Vec<_> = (0..len).step_by(page_size()).map(|offset| mem.add(offset) as *const libc::c_void).collect();
+    let mut status = vec![0i32; pages.len()];
+    // null nodes means query only
+    let res = libc::syscall(libc::SYS_move_pages, 0, pages.len(), pages.as_ptr(), ptr::null::<libc::c_int>(), status.as_mut_ptr(), 0);
+    if res != 0 {
+        return Err(Error::FailMovePages(errno::errno().0))
+    }
+    Ok(status)
+}
+
+/// Format number of pages on each node, e.g. "0:30,1:2".
+/// Pages with error are counted as "err".
+#[inline]
+pub fn format_page_nodes(nodes: &[i32]) -> String {
+    let mut counts = BTreeMap::new();
+    for &node in nodes {
+        *counts.entry(node.max(-1)).or_insert(0usize) += 1;
+    }
+    let items: Vec<_> = counts.into_iter()
+        .map(|(node, n)| if node < 0 { format!("err:{}", n) } else { format!("{}:{}", node, n) })
+        .collect();
+    items.join(",")
+}
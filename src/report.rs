@@ -0,0 +1,414 @@
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::fmt::{self, Write};
+use std::fs;
+use std::mem::MaybeUninit;
+use std::str::FromStr;
+use std::sync::Once;
+use std::time::Duration;
+use crate::{Error, LatencyReport, Result, Summary};
+
+/// Format of result output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Free-form sentences for human.
+    Text,
+    /// One JSON object per line.
+    Json,
+    /// Header line followed by one row per record.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(Error::UnknownOutputFormat),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Print informational message, to stderr if output is structured,
+    /// so that stdout only contains records.
+    #[inline]
+    pub fn info(&self, args: fmt::Arguments<'_>) {
+        if *self == OutputFormat::Text {
+            println!("{}", args)
+        } else {
+            eprintln!("{}", args)
+        }
+    }
+}
+
+/// Value of a field in record.
+#[derive(Debug, Clone)]
+pub enum Field {
+    Str(String),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+}
+
+impl From<&str> for Field {
+    #[inline]
+    fn from(src: &str) -> Self {
+        Field::Str(src.to_string())
+    }
+}
+
+impl From<String> for Field {
+    #[inline]
+    fn from(src: String) -> Self {
+        Field::Str(src)
+    }
+}
+
+impl From<u64> for Field {
+    #[inline]
+    fn from(src: u64) -> Self {
+        Field::U64(src)
+    }
+}
+
+impl From<u32> for Field {
+    #[inline]
+    fn from(src: u32) -> Self {
+        Field::U64(src as u64)
+    }
+}
+
+impl From<usize> for Field {
+    #[inline]
+    fn from(src: usize) -> Self {
+        Field::U64(src as u64)
+    }
+}
+
+impl From<f64> for Field {
+    #[inline]
+    fn from(src: f64) -> Self {
+        Field::F64(src)
+    }
+}
+
+impl From<bool> for Field {
+    #[inline]
+    fn from(src: bool) -> Self {
+        Field::Bool(src)
+    }
+}
+
+/// Duration is recorded in nanoseconds.
+impl From<Duration> for Field {
+    #[inline]
+    fn from(src: Duration) -> Self {
+        Field::U64(src.as_nanos() as u64)
+    }
+}
+
+impl Field {
+    /// Parse value of CSV cell, number and boolean are recognized.
+    #[inline]
+    pub fn parse(s: &str) -> Self {
+        if let Ok(n) = s.parse() {
+            Field::U64(n)
+        } else if let Ok(n) = s.parse() {
+            Field::F64(n)
+        } else if let Ok(b) = s.parse() {
+            Field::Bool(b)
+        } else {
+            Field::Str(s.to_string())
+        }
+    }
+
+    /// Returns numeric value of the field.
+    #[inline]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Field::U64(n) => Some(*n as f64),
+            Field::F64(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Str(s) => f.write_str(s),
+            Field::U64(n) => write!(f, "{}", n),
+            Field::F64(n) => write!(f, "{}", n),
+            Field::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Record is an ordered list of named fields describing one run.
+#[derive(Debug, Clone, Default)]
+pub struct Record {
+    fields: Vec<(Cow<'static, str>, Field)>,
+}
+
+impl Record {
+    #[inline]
+    pub fn new() -> Self {
+        Record::default()
+    }
+
+    #[inline]
+    pub fn push<N: Into<Cow<'static, str>>, F: Into<Field>>(&mut self, name: N, value: F) {
+        self.fields.push((name.into(), value.into()))
+    }
+
+    /// Build record from a CSV row and its header.
+    #[inline]
+    pub fn from_csv(header: &str, row: &str) -> Self {
+        let fields = parse_csv_line(header).into_iter()
+            .zip(parse_csv_line(row))
+            .map(|(name, value)| (Cow::Owned(name), Field::parse(&value)))
+            .collect();
+        Record{fields}
+    }
+
+    /// Push summary of repeated runs, field names are prefixed.
+    #[inline]
+    pub fn push_summary(&mut self, prefix: &str, summary: &Summary) {
+        self.push(format!("{}_mean", prefix), summary.mean);
+        self.push(format!("{}_median", prefix), summary.median);
+        self.push(format!("{}_stddev", prefix), summary.stddev);
+        self.push(format!("{}_ci95", prefix), summary.ci95);
+    }
+
+    /// Push latency percentiles in nanoseconds.
+    #[inline]
+    pub fn push_latency(&mut self, latency: &LatencyReport) {
+        self.push("min_ns", latency.min);
+        self.push("mean_ns", latency.mean);
+        self.push("p50_ns", latency.p50);
+        self.push("p90_ns", latency.p90);
+        self.push("p99_ns", latency.p99);
+        self.push("p999_ns", latency.p999);
+        self.push("p9999_ns", latency.p9999);
+        self.push("max_ns", latency.max);
+    }
+
+    /// Push information of current host.
+    #[inline]
+    pub fn push_host(&mut self, host: &HostInfo) {
+        self.push("hostname", host.hostname.as_str());
+        self.push("kernel", host.kernel.as_str());
+        self.push("cpus", host.cpus);
+        self.push("cpu_model", host.cpu_model.as_str());
+    }
+
+    /// Returns value of the field.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Returns numeric value of the metric, or its mean if runs are
+    /// repeated.
+    #[inline]
+    pub fn metric(&self, name: &str) -> Option<f64> {
+        self.get(name)
+            .or_else(|| self.get(&format!("{}_mean", name)))
+            .and_then(Field::as_f64)
+    }
+
+    #[inline]
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                s.push_str(", ");
+            }
+            _ = write!(s, "{} is {}", name, value);
+        }
+        s
+    }
+
+    #[inline]
+    pub fn to_json(&self) -> String {
+        let mut s = String::from("{");
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            json_str(&mut s, name);
+            s.push(':');
+            match value {
+                Field::Str(v) => json_str(&mut s, v),
+                // JSON has no NaN or infinity
+                Field::F64(v) if !v.is_finite() => s.push_str("null"),
+                _ => { _ = write!(s, "{}", value); }
+            }
+        }
+        s.push('}');
+        s
+    }
+
+    #[inline]
+    pub fn csv_header(&self) -> String {
+        let names: Vec<_> = self.fields.iter().map(|(name, _)| csv_str(name)).collect();
+        names.join(",")
+    }
+
+    #[inline]
+    pub fn to_csv(&self) -> String {
+        let values: Vec<_> = self.fields.iter().map(|(_, value)| csv_str(&value.to_string())).collect();
+        values.join(",")
+    }
+}
+
+#[inline]
+fn json_str(s: &mut String, v: &str) {
+    s.push('"');
+    for c in v.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => { _ = write!(s, "\\u{:04x}", c as u32); }
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+}
+
+#[inline]
+fn csv_str(v: &str) -> String {
+    if v.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", v.replace('"', "\"\""))
+    } else {
+        v.to_string()
+    }
+}
+
+/// Split a CSV line into cells, quotes are removed.
+#[inline]
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+/// Printer prints records in given format, CSV header is printed
+/// before the first record only, so it can be shared by threads.
+pub struct Printer {
+    format: OutputFormat,
+    header: Once,
+}
+
+impl Printer {
+    #[inline]
+    pub fn new(format: OutputFormat) -> Self {
+        Printer{format, header: Once::new()}
+    }
+
+    #[inline]
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    #[inline]
+    pub fn print(&self, record: &Record) {
+        match self.format {
+            OutputFormat::Text => println!("{}", record.to_text()),
+            OutputFormat::Json => println!("{}", record.to_json()),
+            OutputFormat::Csv => {
+                self.header.call_once(|| println!("{}", record.csv_header()));
+                println!("{}", record.to_csv())
+            }
+        }
+    }
+}
+
+/// Information of the host running benchmark.
+#[derive(Debug, Clone)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub kernel: String,
+    pub cpus: usize,
+    pub cpu_model: String,
+}
+
+impl HostInfo {
+    /// Collect host information, unknown fields are left empty.
+    #[inline]
+    pub fn collect() -> Self {
+        let mut hostname = String::new();
+        let mut kernel = String::new();
+        unsafe {
+            let mut uts = MaybeUninit::<libc::utsname>::zeroed();
+            if libc::uname(uts.as_mut_ptr()) == 0 {
+                let uts = uts.assume_init();
+                hostname = CStr::from_ptr(uts.nodename.as_ptr()).to_string_lossy().into_owned();
+                kernel = CStr::from_ptr(uts.release.as_ptr()).to_string_lossy().into_owned();
+            }
+        }
+        let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(0);
+        let cpu_model = fs::read_to_string("/proc/cpuinfo").ok()
+            .and_then(|s| s.lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, model)| model.trim().to_string()))
+            .unwrap_or_default();
+        HostInfo{hostname, kernel, cpus, cpu_model}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut record = Record::new();
+        record.push("transport", "shm");
+        record.push("cpu", "0,1");
+        record.push("peer", "say \"hi\"");
+        record.push("num", 1024u64);
+        record.push("throughput", 1234.5);
+        record.push("spin_only", true);
+        let loaded = Record::from_csv(&record.csv_header(), &record.to_csv());
+        assert_eq!(loaded.csv_header(), record.csv_header());
+        assert_eq!(loaded.to_csv(), record.to_csv());
+        assert_eq!(loaded.get("cpu").unwrap().to_string(), "0,1");
+        assert_eq!(loaded.get("peer").unwrap().to_string(), "say \"hi\"");
+        assert!(matches!(loaded.get("num"), Some(Field::U64(1024))));
+        assert_eq!(loaded.metric("throughput"), Some(1234.5));
+        assert!(matches!(loaded.get("spin_only"), Some(Field::Bool(true))));
+    }
+
+    #[test]
+    fn test_metric_of_repeated_runs() {
+        let mut record = Record::new();
+        record.push_summary("throughput", &Summary{n: 3, mean: 10.0, median: 9.0, stddev: 1.0, ci95: 0.5});
+        let loaded = Record::from_csv(&record.csv_header(), &record.to_csv());
+        assert_eq!(loaded.metric("throughput"), Some(10.0));
+        assert_eq!(loaded.metric("p50_ns"), None);
+    }
+}
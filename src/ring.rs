@@ -0,0 +1,528 @@
+use std::cell::UnsafeCell;
+use std::mem::{size_of, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicI32, AtomicU8, AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crossbeam_utils::{Backoff, CachePadded};
+use libc::pid_t;
+use crate::{
+    futex_wait, futex_wake, pid_alive, CabinGuard, Error, Result, SegmentKind, WaitBackend,
+    LIVENESS_CHECK_INTERVAL,
+};
+use crate::header::ShmHeader;
+
+/// Default capacity of ring, must be power of two.
+pub const RING_CAPACITY: usize = 1024;
+
+/// Lock-free single-producer/single-consumer ring in shared memory.
+/// Head and tail are on separate cache lines so producer and consumer
+/// do not invalidate each other's cache line on every update.
+#[repr(C)]
+pub struct Ring<T, const N: usize> {
+    // next position to pop, only updated by consumer.
+    head: CachePadded<AtomicUsize>,
+    // next position to push, only updated by producer.
+    tail: CachePadded<AtomicUsize>,
+    buf: [UnsafeCell<MaybeUninit<T>>; N],
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Ring<T, N> {}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "ring capacity must be power of two");
+        N - 1
+    };
+
+    /// Try to push a value, returns false if ring is full.
+    /// Must only be called by the producer.
+    #[inline]
+    pub fn try_push(&self, value: T) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return false
+        }
+        unsafe { (*self.buf[tail & Self::MASK].get()).write(value); }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Try to pop a value, returns None if ring is empty.
+    /// Must only be called by the consumer.
+    #[inline]
+    pub fn try_pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None
+        }
+        let value = unsafe { (*self.buf[head & Self::MASK].get()).assume_init() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Push values as many as possible, returns number of values pushed.
+    /// Must only be called by the producer.
+    #[inline]
+    pub fn push_slice(&self, values: &[T]) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let n = values.len().min(N - tail.wrapping_sub(head));
+        if n == 0 {
+            return 0
+        }
+        // copy in two parts if wrapped around
+        let start = tail & Self::MASK;
+        let first = n.min(N - start);
+        unsafe {
+            let buf = self.buf.as_ptr() as *mut T;
+            ptr::copy_nonoverlapping(values.as_ptr(), buf.add(start), first);
+            ptr::copy_nonoverlapping(values.as_ptr().add(first), buf, n - first);
+        }
+        self.tail.store(tail.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    /// Pop values as many as possible, returns number of values popped.
+    /// Must only be called by the consumer.
+    #[inline]
+    pub fn pop_slice(&self, values: &mut [T]) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        let n = values.len().min(tail.wrapping_sub(head));
+        if n == 0 {
+            return 0
+        }
+        // copy in two parts if wrapped around
+        let start = head & Self::MASK;
+        let first = n.min(N - start);
+        unsafe {
+            let buf = self.buf.as_ptr() as *const T;
+            ptr::copy_nonoverlapping(buf.add(start), values.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(buf, values.as_mut_ptr().add(first), n - first);
+        }
+        self.head.store(head.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    /// Push a value, spin if ring is full.
+    #[inline]
+    pub fn push(&self, value: T) {
+        if self.try_push(value) {
+            return
+        }
+        let backoff = Backoff::new();
+        while !self.try_push(value) {
+            backoff.snooze();
+        }
+    }
+
+    /// Pop a value, spin if ring is empty.
+    #[inline]
+    pub fn pop(&self) -> T {
+        if let Some(value) = self.try_pop() {
+            return value
+        }
+        let backoff = Backoff::new();
+        loop {
+            backoff.snooze();
+            if let Some(value) = self.try_pop() {
+                return value
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// Reset the ring to empty, only when no producer and consumer is active.
+    #[inline]
+    fn reset(&self) {
+        self.head.store(0, Ordering::Relaxed);
+        self.tail.store(0, Ordering::Relaxed);
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingState {
+    Accepting = 0,
+    Connected = 1,
+    Disconnected = 2,
+    /// Client claimed the channel and is publishing its id and pid.
+    Connecting = 3,
+}
+
+impl From<u8> for RingState {
+    #[inline]
+    fn from(src: u8) -> Self {
+        use RingState::*;
+        match src {
+            0 => Accepting,
+            1 => Connected,
+            3 => Connecting,
+            _ => Disconnected,
+        }
+    }
+}
+
+/// RingChannel contains a request ring and a response ring, so that
+/// client can send requests without waiting for responses.
+/// Waiters of futex backend block on the event sequence, which is
+/// advanced on every push and pop.
+#[repr(C)]
+pub struct RingChannel<T, U, const N: usize = RING_CAPACITY> {
+    state: CachePadded<AtomicU8>,
+    id: AtomicU32,
+    client_pid: AtomicI32,
+    server_pid: AtomicI32,
+    backend: AtomicU8,
+    event: CachePadded<AtomicU32>,
+    sleepers: AtomicU32,
+    req: Ring<T, N>,
+    resp: Ring<U, N>,
+}
+
+impl<T: Copy, U: Copy, const N: usize> RingChannel<T, U, N> {
+    /// Returns memory size required to hold the channel.
+    #[inline]
+    pub fn mem_size() -> usize {
+        ShmHeader::mem_size::<Self>(size_of::<Self>())
+    }
+
+    /// Initialize a channel in given memory with wait backend.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to zeroed shared memory of at least
+    /// [`RingChannel::mem_size`] bytes, and must outlive the guard.
+    #[inline]
+    pub unsafe fn new(mem: *mut u8, backend: WaitBackend, _guard: &CabinGuard) -> &Self {
+        let (header, ptr) = ShmHeader::init::<T, U, Self>(mem, SegmentKind::RingChannel);
+        // zeroed memory is already a valid empty channel in accepting state.
+        let chan = &*(ptr as *const Self);
+        chan.backend.store(backend as u8, Ordering::Relaxed);
+        chan.server_pid.store(std::process::id() as pid_t, Ordering::Relaxed);
+        header.mark_initialized();
+        chan
+    }
+
+    /// Attach to a channel already initialized by another process.
+    /// Header is validated so that mismatched `T`, `U`, `N` or stale
+    /// segment are rejected.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must point to shared memory of at least header size,
+    /// and must outlive the guard.
+    #[inline]
+    pub unsafe fn from_existing(mem: *mut u8, _guard: &CabinGuard) -> Result<&Self> {
+        let (_, ptr) = ShmHeader::attach::<T, U, Self>(mem, SegmentKind::RingChannel)?;
+        Ok(&*(ptr as *const Self))
+    }
+
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn load_state(&self, order: Ordering) -> RingState {
+        self.state.load(order).into()
+    }
+
+    #[inline]
+    pub fn client_pid(&self) -> pid_t {
+        self.client_pid.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_client_pid(&self, pid: pid_t) {
+        self.client_pid.store(pid, Ordering::Relaxed)
+    }
+
+    /// Returns whether client process is still alive.
+    /// Returns true if no client process is recorded.
+    #[inline]
+    pub fn client_alive(&self) -> bool {
+        pid_alive(self.client_pid())
+    }
+
+    #[inline]
+    pub fn server_pid(&self) -> pid_t {
+        self.server_pid.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_server_pid(&self, pid: pid_t) {
+        self.server_pid.store(pid, Ordering::Relaxed)
+    }
+
+    /// Returns whether server process is still alive.
+    /// Returns true if no server process is recorded.
+    #[inline]
+    pub fn server_alive(&self) -> bool {
+        pid_alive(self.server_pid())
+    }
+
+    /// Returns wait backend selected by server. Only
+    /// [`ShmStream`](crate::ShmStream) blocks with futex backend, other
+    /// users of the channel spin.
+    #[inline]
+    pub fn backend(&self) -> WaitBackend {
+        self.backend.load(Ordering::Relaxed).into()
+    }
+
+    /// Returns current event sequence. Waiter must load it before checking
+    /// the rings, so that events after the check wake it up.
+    #[inline]
+    pub fn event_seq(&self) -> u32 {
+        self.event.load(Ordering::SeqCst)
+    }
+
+    /// Block until event sequence moves past `seq` or timeout.
+    #[inline]
+    pub fn wait_event(&self, seq: u32, timeout: Duration) -> Result<()> {
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        let res = futex_wait(&self.event, seq, timeout);
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    /// Advance event sequence after push, pop or disconnection, and wake
+    /// up waiters if any.
+    #[inline]
+    pub fn notify(&self) -> Result<()> {
+        self.event.fetch_add(1, Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            futex_wake(&self.event)?;
+        }
+        Ok(())
+    }
+
+    /// Connect to server, fails if another client already connected.
+    /// Id and pid of client are published before server sees the
+    /// connection.
+    #[inline]
+    pub fn connect(&self, id: u32) -> Result<()> {
+        self.state.compare_exchange(RingState::Accepting as u8, RingState::Connecting as u8, Ordering::AcqRel, Ordering::Relaxed)
+            .map_err(|_| Error::ChannelBusy)?;
+        self.id.store(id, Ordering::Relaxed);
+        self.client_pid.store(std::process::id() as pid_t, Ordering::Relaxed);
+        self.state.store(RingState::Connected as u8, Ordering::Release);
+        Ok(())
+    }
+
+    /// Disconnect from server, requests already sent will still be processed.
+    /// Only connected channel is disconnected, so that disconnecting twice
+    /// does not break session of next client.
+    #[inline]
+    pub fn disconnect(&self) {
+        if self.state.compare_exchange(RingState::Connected as u8, RingState::Disconnected as u8, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+            // server may block on futex
+            _ = self.notify();
+        }
+    }
+
+    /// Wait until a client connects, returns client id.
+    #[inline]
+    pub fn accept(&self) -> u32 {
+        let backoff = Backoff::new();
+        while matches!(self.load_state(Ordering::Acquire), RingState::Accepting | RingState::Connecting) {
+            backoff.snooze();
+        }
+        self.id()
+    }
+
+    /// Reset the channel to accept next client.
+    #[inline]
+    pub fn reset(&self) {
+        self.req.reset();
+        self.resp.reset();
+        self.client_pid.store(0, Ordering::Relaxed);
+        self.state.store(RingState::Accepting as u8, Ordering::Release)
+    }
+
+    /// Returns ring of requests.
+    #[inline]
+    pub fn req(&self) -> &Ring<T, N> {
+        &self.req
+    }
+
+    /// Returns ring of responses.
+    #[inline]
+    pub fn resp(&self) -> &Ring<U, N> {
+        &self.resp
+    }
+
+    /// Send request, spin if request ring is full.
+    #[inline]
+    pub fn send_req(&self, req: T) {
+        self.req.push(req)
+    }
+
+    /// Receive request, returns None if client disconnected and
+    /// all requests are consumed.
+    /// Client liveness is checked periodically once spinning is done,
+    /// returns [`Error::ClientDead`] if client process died.
+    #[inline]
+    pub fn recv_req(&self) -> Result<Option<T>> {
+        let backoff = Backoff::new();
+        let mut checked = Instant::now();
+        loop {
+            if let Some(req) = self.req.try_pop() {
+                return Ok(Some(req))
+            }
+            if self.load_state(Ordering::Acquire) == RingState::Disconnected {
+                // requests may be pushed right before disconnection
+                return Ok(self.req.try_pop())
+            }
+            if backoff.is_completed() && checked.elapsed() >= LIVENESS_CHECK_INTERVAL {
+                if !self.client_alive() {
+                    return Err(Error::ClientDead)
+                }
+                checked = Instant::now();
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Send response, spin if response ring is full.
+    #[inline]
+    pub fn send_resp(&self, resp: U) {
+        self.resp.push(resp)
+    }
+
+    /// Receive response, spin if response ring is empty.
+    /// Server liveness is checked periodically once spinning is done,
+    /// returns [`Error::ServerDead`] if server process died.
+    #[inline]
+    pub fn recv_resp(&self) -> Result<U> {
+        let backoff = Backoff::new();
+        let mut checked = Instant::now();
+        loop {
+            if let Some(resp) = self.resp.try_pop() {
+                return Ok(resp)
+            }
+            if backoff.is_completed() && checked.elapsed() >= LIVENESS_CHECK_INTERVAL {
+                if !self.server_alive() {
+                    return Err(Error::ServerDead)
+                }
+                checked = Instant::now();
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_pid;
+
+    /// Zeroed memory is an empty ring, as in a new segment.
+    fn new_ring<const N: usize>() -> Box<Ring<u8, N>> {
+        Box::new(unsafe { std::mem::zeroed() })
+    }
+
+    #[test]
+    fn test_ring_empty_and_full() {
+        let ring = new_ring::<8>();
+        let mut out = [0u8; 8];
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop_slice(&mut out), 0);
+        assert_eq!(ring.try_pop(), None);
+        // only capacity is pushed
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]), 8);
+        assert_eq!(ring.push_slice(&[11]), 0);
+        assert!(!ring.try_push(11));
+        assert_eq!(ring.pop_slice(&mut out), 8);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop_slice(&mut out), 0);
+    }
+
+    #[test]
+    fn test_ring_partial_slices() {
+        let ring = new_ring::<8>();
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5]), 5);
+        // only 3 slots left
+        assert_eq!(ring.push_slice(&[6, 7, 8, 9]), 3);
+        let mut out = [0u8; 3];
+        assert_eq!(ring.pop_slice(&mut out), 3);
+        assert_eq!(out, [1, 2, 3]);
+        let mut out = [0u8; 8];
+        assert_eq!(ring.pop_slice(&mut out), 5);
+        assert_eq!(&out[..5], &[4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_ring_wraparound() {
+        let ring = new_ring::<8>();
+        let mut out = [0u8; 8];
+        // move head and tail to position 6
+        assert_eq!(ring.push_slice(&[0; 6]), 6);
+        assert_eq!(ring.pop_slice(&mut out[..6]), 6);
+        // 2 values at the end and 6 at the start
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5, 6, 7, 8]), 8);
+        assert!(!ring.try_push(9));
+        assert_eq!(ring.try_pop(), Some(1));
+        assert!(ring.try_push(9));
+        assert_eq!(ring.pop_slice(&mut out), 8);
+        assert_eq!(out, [2, 3, 4, 5, 6, 7, 8, 9]);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_ring_counter_overflow() {
+        let ring = new_ring::<8>();
+        // positions wrap around usize while values wrap around buffer
+        ring.head.store(usize::MAX - 2, Ordering::Relaxed);
+        ring.tail.store(usize::MAX - 2, Ordering::Relaxed);
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5, 6, 7, 8]), 8);
+        assert_eq!(ring.push_slice(&[9]), 0);
+        let mut out = [0u8; 8];
+        assert_eq!(ring.pop_slice(&mut out), 8);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_channel_client_liveness() {
+        let chan: Box<RingChannel<u64, u64, 8>> = Box::new(unsafe { std::mem::zeroed() });
+        assert!(chan.client_alive());
+        chan.connect(7).unwrap();
+        assert!(matches!(chan.connect(8), Err(Error::ChannelBusy)));
+        assert_eq!(chan.accept(), 7);
+        assert_eq!(chan.client_pid(), std::process::id() as pid_t);
+        assert!(chan.client_alive());
+        chan.send_req(1);
+        assert_eq!(chan.recv_req().unwrap(), Some(1));
+        chan.disconnect();
+        assert_eq!(chan.recv_req().unwrap(), None);
+        chan.reset();
+        assert_eq!(chan.client_pid(), 0);
+        assert_eq!(chan.load_state(Ordering::Acquire), RingState::Accepting);
+        // disconnecting again does not touch channel of next client
+        chan.disconnect();
+        assert_eq!(chan.load_state(Ordering::Acquire), RingState::Accepting);
+        chan.connect(8).unwrap();
+        chan.disconnect();
+        assert_eq!(chan.load_state(Ordering::Acquire), RingState::Disconnected);
+    }
+
+    #[test]
+    fn test_channel_server_liveness() {
+        let chan: Box<RingChannel<u64, u64, 8>> = Box::new(unsafe { std::mem::zeroed() });
+        assert!(chan.server_alive());
+        chan.connect(7).unwrap();
+        chan.send_resp(1);
+        assert_eq!(chan.recv_resp().unwrap(), 1);
+        chan.set_server_pid(dead_pid());
+        assert!(!chan.server_alive());
+        assert!(matches!(chan.recv_resp(), Err(Error::ServerDead)));
+    }
+}
@@ -0,0 +1,258 @@
+use std::cell::Cell;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use shared_memory::{Shmem, ShmemConf};
+use libc::pid_t;
+use crate::{
+    numa, ByteCabin, Cabin, CabinGuard, CabinPool, Error, Result, RingChannel, WaitBackend,
+    RING_CAPACITY,
+};
+
+/// Create a segment linked by the flink file, pages are bound to the node
+/// if specified, and prefaulted before initialization.
+#[inline]
+fn create_segment<P: AsRef<Path>>(flink: P, size: usize, node: Option<usize>) -> Result<Shmem> {
+    let shmem = ShmemConf::new().size(size).flink(flink).create()?;
+    unsafe {
+        if let Some(node) = node {
+            numa::bind_node(shmem.as_ptr(), shmem.len(), node)?;
+        }
+        numa::prefault(shmem.as_ptr(), shmem.len());
+    }
+    Ok(shmem)
+}
+
+/// Returns node of each page of the segment.
+#[inline]
+fn segment_nodes(shmem: &Shmem) -> Result<Vec<i32>> {
+    unsafe { numa::page_nodes(shmem.as_ptr(), shmem.len()) }
+}
+
+/// Segment owns the mapping of a cabin pool.
+struct Segment<T, U> {
+    shmem: Shmem,
+    pool: *const CabinPool<T, U>,
+}
+
+// The mapping is shared by all threads of the process, and cabins
+// synchronize via state machine.
+unsafe impl<T: Send, U: Send> Send for Segment<T, U> {}
+unsafe impl<T: Send, U: Send> Sync for Segment<T, U> {}
+
+impl<T, U> Segment<T, U> {
+    #[inline]
+    fn pool(&self) -> &CabinPool<T, U> {
+        unsafe { &*self.pool }
+    }
+}
+
+impl<T, U> Drop for Segment<T, U> {
+    fn drop(&mut self) {
+        // creator destroys pthread objects before the mapping is removed
+        if self.shmem.is_owner() {
+            for cabin in self.pool().cabins() {
+                unsafe { cabin.destroy(); }
+            }
+        }
+    }
+}
+
+/// ShmCabin is an owned handle of one cabin in a shared memory segment.
+/// It keeps the mapping alive as long as any handle of the segment exists,
+/// so the cabin can be used without unsafe code.
+pub struct ShmCabin<T, U> {
+    seg: Arc<Segment<T, U>>,
+    slot: usize,
+    /// Claim of client not yet taken over by server.
+    claimed: Cell<bool>,
+}
+
+impl<T, U> ShmCabin<T, U> {
+    /// Create a segment with a pool of cabins linked by the flink file,
+    /// returns one handle for each slot.
+    /// The flink file is removed when all handles are dropped.
+    /// Pages are bound to the NUMA node if specified.
+    #[inline]
+    pub fn create<P: AsRef<Path>>(flink: P, slots: usize, backend: WaitBackend, node: Option<usize>) -> Result<Vec<Self>> {
+        let shmem = create_segment(flink, CabinPool::<T, U>::mem_size(slots), node)?;
+        let guard = CabinGuard;
+        let pool = unsafe { CabinPool::<T, U>::new(shmem.as_ptr(), slots, backend, &guard)? } as *const _;
+        let seg = Arc::new(Segment{shmem, pool});
+        Ok((0..slots).map(|slot| ShmCabin{seg: Arc::clone(&seg), slot, claimed: Cell::new(false)}).collect())
+    }
+
+    /// Open the segment linked by the flink file and claim a free cabin.
+    /// The claim is released when the handle is dropped before connecting.
+    #[inline]
+    pub fn open<P: AsRef<Path>>(flink: P) -> Result<Self> {
+        let shmem = ShmemConf::new().flink(flink).open()?;
+        let guard = CabinGuard;
+        let pool = unsafe { CabinPool::<T, U>::from_existing(shmem.as_ptr(), &guard)? } as *const _;
+        let seg = Segment{shmem, pool};
+        let (slot, _) = seg.pool().claim()?;
+        Ok(ShmCabin{seg: Arc::new(seg), slot, claimed: Cell::new(true)})
+    }
+
+    /// Mark the client connected, server releases the claim after it
+    /// resets the cabin.
+    #[inline]
+    pub(crate) fn set_connected(&self) {
+        self.claimed.set(false)
+    }
+
+    /// Returns slot number of the cabin in pool.
+    #[inline]
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Returns NUMA node of each page of the segment.
+    #[inline]
+    pub fn page_nodes(&self) -> Result<Vec<i32>> {
+        segment_nodes(&self.seg.shmem)
+    }
+}
+
+impl<T, U> Deref for ShmCabin<T, U> {
+    type Target = Cabin<T, U>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.seg.pool().cabins()[self.slot]
+    }
+}
+
+impl<T, U> Drop for ShmCabin<T, U> {
+    fn drop(&mut self) {
+        if self.claimed.get() {
+            self.set_client_pid(0);
+            self.release();
+        }
+    }
+}
+
+/// ShmByteCabin is an owned handle of a byte cabin in a shared memory
+/// segment, it derefs to the inner cabin so that it can be driven by
+/// [`CabinServer`](crate::CabinServer) and [`CabinClient`](crate::CabinClient).
+pub struct ShmByteCabin {
+    shmem: Shmem,
+    cabin: *const ByteCabin,
+    /// Claim of client not yet taken over by server.
+    claimed: Cell<bool>,
+}
+
+// The cabin synchronizes via state machine.
+unsafe impl Send for ShmByteCabin {}
+
+impl ShmByteCabin {
+    /// Create a segment with a byte cabin of given capacity linked by the
+    /// flink file. The flink file is removed when the handle is dropped.
+    /// Pages are bound to the NUMA node if specified.
+    #[inline]
+    pub fn create<P: AsRef<Path>>(flink: P, capacity: usize, backend: WaitBackend, node: Option<usize>) -> Result<Self> {
+        let shmem = create_segment(flink, ByteCabin::mem_size(capacity), node)?;
+        let guard = CabinGuard;
+        let cabin = unsafe { ByteCabin::new(shmem.as_ptr(), capacity, backend, &guard)? } as *const _;
+        Ok(ShmByteCabin{shmem, cabin, claimed: Cell::new(false)})
+    }
+
+    /// Open the segment linked by the flink file and claim the cabin.
+    /// The claim is released when the handle is dropped before connecting.
+    #[inline]
+    pub fn open<P: AsRef<Path>>(flink: P) -> Result<Self> {
+        let shmem = ShmemConf::new().flink(flink).open()?;
+        let guard = CabinGuard;
+        let cabin = unsafe { ByteCabin::from_existing(shmem.as_ptr(), shmem.len(), &guard)? } as *const _;
+        let handle = ShmByteCabin{shmem, cabin, claimed: Cell::new(false)};
+        if !handle.try_claim() {
+            return Err(Error::NoFreeCabin)
+        }
+        handle.set_client_pid(std::process::id() as pid_t);
+        handle.claimed.set(true);
+        Ok(handle)
+    }
+
+    /// Mark the client connected, server releases the claim after it
+    /// resets the cabin.
+    #[inline]
+    pub(crate) fn set_connected(&self) {
+        self.claimed.set(false)
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> &ByteCabin {
+        unsafe { &*self.cabin }
+    }
+
+    /// Returns NUMA node of each page of the segment.
+    #[inline]
+    pub fn page_nodes(&self) -> Result<Vec<i32>> {
+        segment_nodes(&self.shmem)
+    }
+}
+
+impl Deref for ShmByteCabin {
+    type Target = Cabin<usize, usize>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.bytes()
+    }
+}
+
+impl Drop for ShmByteCabin {
+    fn drop(&mut self) {
+        if self.claimed.get() {
+            self.set_client_pid(0);
+            self.release();
+        }
+        // creator destroys pthread objects before the mapping is removed
+        if self.shmem.is_owner() {
+            unsafe { self.bytes().destroy(); }
+        }
+    }
+}
+
+/// ShmRing is an owned handle of a ring channel in a shared memory segment.
+pub struct ShmRing<T, U, const N: usize = RING_CAPACITY> {
+    shmem: Shmem,
+    chan: *const RingChannel<T, U, N>,
+}
+
+// The channel synchronizes via atomic state and rings.
+unsafe impl<T: Send, U: Send, const N: usize> Send for ShmRing<T, U, N> {}
+
+impl<T: Copy, U: Copy, const N: usize> ShmRing<T, U, N> {
+    /// Create a segment with a ring channel linked by the flink file.
+    /// The flink file is removed when the handle is dropped.
+    /// Pages are bound to the NUMA node if specified.
+    #[inline]
+    pub fn create<P: AsRef<Path>>(flink: P, backend: WaitBackend, node: Option<usize>) -> Result<Self> {
+        let shmem = create_segment(flink, RingChannel::<T, U, N>::mem_size(), node)?;
+        let guard = CabinGuard;
+        let chan = unsafe { RingChannel::<T, U, N>::new(shmem.as_ptr(), backend, &guard) } as *const _;
+        Ok(ShmRing{shmem, chan})
+    }
+
+    /// Open the segment linked by the flink file.
+    #[inline]
+    pub fn open<P: AsRef<Path>>(flink: P) -> Result<Self> {
+        let shmem = ShmemConf::new().flink(flink).open()?;
+        let guard = CabinGuard;
+        let chan = unsafe { RingChannel::<T, U, N>::from_existing(shmem.as_ptr(), &guard)? } as *const _;
+        Ok(ShmRing{shmem, chan})
+    }
+
+    /// Returns NUMA node of each page of the segment.
+    #[inline]
+    pub fn page_nodes(&self) -> Result<Vec<i32>> {
+        segment_nodes(&self.shmem)
+    }
+}
+
+impl<T, U, const N: usize> Deref for ShmRing<T, U, N> {
+    type Target = RingChannel<T, U, N>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.chan }
+    }
+}
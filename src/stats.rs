@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// Two-sided 95% quantiles of Student's t-distribution for degrees of
+/// freedom 1 to 30, normal distribution is used beyond that.
+const T95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Summary of samples collected by repeated runs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// Sample standard deviation.
+    pub stddev: f64,
+    /// Half width of 95% confidence interval of mean.
+    pub ci95: f64,
+}
+
+impl Summary {
+    #[inline]
+    pub fn of(samples: &[f64]) -> Self {
+        let n = samples.len();
+        if n == 0 {
+            return Summary::default()
+        }
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        };
+        if n == 1 {
+            return Summary{n, mean, median, stddev: 0.0, ci95: 0.0}
+        }
+        let var = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1) as f64;
+        let stddev = var.sqrt();
+        let t = T95.get(n - 2).copied().unwrap_or(1.96);
+        let ci95 = t * stddev / (n as f64).sqrt();
+        Summary{n, mean, median, stddev, ci95}
+    }
+}
+
+impl fmt::Display for Summary {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mean is {:.1}, median is {:.1}, stddev is {:.1}, 95% ci is ±{:.1}",
+            self.mean, self.median, self.stddev, self.ci95)
+    }
+}
@@ -0,0 +1,238 @@
+use std::hint;
+use std::io::{self, ErrorKind, Read, Write};
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use crossbeam_utils::Backoff;
+use crate::{Error, Ring, RingChannel, RingState, WaitBackend, LIVENESS_CHECK_INTERVAL};
+
+/// Capacity in bytes of each direction of a shared memory stream.
+pub const STREAM_CAPACITY: usize = 64 * 1024;
+
+/// Channel of bytes backing [`ShmStream`].
+pub type StreamChannel = RingChannel<u8, u8, STREAM_CAPACITY>;
+
+/// Number of waits between reads of clock for liveness check.
+const CLOCK_CHECK_WAITS: u32 = 1024;
+
+/// State of waiting for peer in one read or write.
+#[derive(Default)]
+struct Wait {
+    backoff: Backoff,
+    count: u32,
+    /// Time of last liveness check, set when waiting starts.
+    checked: Option<Instant>,
+}
+
+/// ShmStream is a byte stream over shared memory ring channel, so
+/// everything built on `Read` and `Write` works as with `UnixStream`.
+/// Client writes requests and reads responses, server does the opposite.
+/// Waits spin, and then yield the thread unless `spin_only` is set.
+/// If the channel is created with futex backend, waits block on futex
+/// after spinning instead of yielding, and each side wakes up the other
+/// after reading or writing.
+pub struct ShmStream<C: Deref<Target = StreamChannel>> {
+    chan: C,
+    server: bool,
+    spin_only: bool,
+    futex: bool,
+}
+
+impl<C: Deref<Target = StreamChannel>> ShmStream<C> {
+    /// Create client end of the stream, channel must be connected.
+    #[inline]
+    pub fn client(chan: C, spin_only: bool) -> Self {
+        let futex = chan.backend() == WaitBackend::Futex;
+        ShmStream{chan, server: false, spin_only, futex}
+    }
+
+    /// Create server end of the stream, channel must be accepted.
+    #[inline]
+    pub fn server(chan: C, spin_only: bool) -> Self {
+        let futex = chan.backend() == WaitBackend::Futex;
+        ShmStream{chan, server: true, spin_only, futex}
+    }
+
+    #[inline]
+    fn rx(&self) -> &Ring<u8, STREAM_CAPACITY> {
+        if self.server { self.chan.req() } else { self.chan.resp() }
+    }
+
+    #[inline]
+    fn tx(&self) -> &Ring<u8, STREAM_CAPACITY> {
+        if self.server { self.chan.resp() } else { self.chan.req() }
+    }
+
+    #[inline]
+    fn peer_closed(&self) -> bool {
+        // only client closes the channel
+        self.server && self.chan.load_state(Ordering::Acquire) == RingState::Disconnected
+    }
+
+    /// Wait for peer to read or write, `seq` is event sequence loaded
+    /// before the ring was checked.
+    /// Liveness of peer is checked periodically in all wait modes, fails
+    /// with [`Error::ClientDead`] or [`Error::ServerDead`] if peer died.
+    #[inline]
+    fn wait(&self, wait: &mut Wait, seq: u32) -> io::Result<()> {
+        let checked = *wait.checked.get_or_insert_with(Instant::now);
+        wait.count = wait.count.wrapping_add(1);
+        let blocking = self.futex && !self.spin_only && wait.backoff.is_completed();
+        if self.spin_only {
+            hint::spin_loop();
+        } else if blocking {
+            self.chan.wait_event(seq, LIVENESS_CHECK_INTERVAL).map_err(io::Error::other)?;
+        } else {
+            wait.backoff.snooze();
+        }
+        // blocking wait returns at least once per interval, while spinning
+        // only reads clock every some waits
+        if !(blocking || wait.count.is_multiple_of(CLOCK_CHECK_WAITS)) {
+            return Ok(())
+        }
+        if checked.elapsed() >= LIVENESS_CHECK_INTERVAL {
+            if self.server && !self.chan.client_alive() {
+                return Err(io::Error::new(ErrorKind::ConnectionAborted, Error::ClientDead))
+            }
+            if !self.server && !self.chan.server_alive() {
+                return Err(io::Error::new(ErrorKind::ConnectionAborted, Error::ServerDead))
+            }
+            wait.checked = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Wake up peer blocking on futex.
+    #[inline]
+    fn notify(&self) -> io::Result<()> {
+        if self.futex {
+            self.chan.notify().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: Deref<Target = StreamChannel>> Read for ShmStream<C> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        let mut wait = Wait::default();
+        loop {
+            let seq = self.chan.event_seq();
+            let n = self.rx().pop_slice(buf);
+            if n > 0 {
+                self.notify()?;
+                return Ok(n)
+            }
+            if self.peer_closed() {
+                // bytes may be written right before disconnection, returns 0 as EOF if none
+                return Ok(self.rx().pop_slice(buf))
+            }
+            self.wait(&mut wait, seq)?;
+        }
+    }
+}
+
+impl<C: Deref<Target = StreamChannel>> Write for ShmStream<C> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        let mut wait = Wait::default();
+        loop {
+            let seq = self.chan.event_seq();
+            let n = self.tx().push_slice(buf);
+            if n > 0 {
+                self.notify()?;
+                return Ok(n)
+            }
+            if self.peer_closed() {
+                return Err(ErrorKind::BrokenPipe.into())
+            }
+            self.wait(&mut wait, seq)?;
+        }
+    }
+
+    /// Bytes are visible to peer once written.
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<C: Deref<Target = StreamChannel>> Drop for ShmStream<C> {
+    fn drop(&mut self) {
+        if self.server {
+            // accept next client
+            self.chan.reset();
+        } else {
+            self.chan.disconnect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_pid;
+
+    /// Zeroed memory is an empty channel in accepting state.
+    fn new_channel() -> Box<StreamChannel> {
+        Box::new(unsafe { std::mem::zeroed() })
+    }
+
+    #[test]
+    fn test_stream_read_write() {
+        let chan = new_channel();
+        chan.connect(1).unwrap();
+        assert_eq!(chan.accept(), 1);
+        let mut client = ShmStream::client(&*chan, true);
+        let mut server = ShmStream::server(&*chan, true);
+        client.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(server.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        server.write_all(b"world").unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+        // server sees EOF after client disconnects
+        drop(client);
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+    }
+
+    fn assert_client_dead(spin_only: bool) {
+        let chan = new_channel();
+        chan.connect(1).unwrap();
+        chan.accept();
+        chan.set_client_pid(dead_pid());
+        let mut server = ShmStream::server(&*chan, spin_only);
+        let err = server.read(&mut [0u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+        assert!(matches!(err.into_inner().unwrap().downcast::<Error>().as_deref(), Ok(Error::ClientDead)));
+    }
+
+    #[test]
+    fn test_stream_client_dead_when_spinning() {
+        assert_client_dead(true);
+    }
+
+    #[test]
+    fn test_stream_client_dead_when_yielding() {
+        assert_client_dead(false);
+    }
+
+    #[test]
+    fn test_stream_server_dead() {
+        let chan = new_channel();
+        chan.connect(1).unwrap();
+        chan.accept();
+        chan.set_server_pid(dead_pid());
+        let mut client = ShmStream::client(&*chan, false);
+        let err = client.read(&mut [0u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+        assert!(matches!(err.into_inner().unwrap().downcast::<Error>().as_deref(), Ok(Error::ServerDead)));
+    }
+}
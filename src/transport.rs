@@ -0,0 +1,737 @@
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Deref;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{
+    Cabin, CabinClient, CabinServer, Error, Result, RingChannel, ShmByteCabin, ShmCabin, ShmRing,
+    ShmStream, UringConn, WaitBackend, PAYLOAD_FLAG, RING_CAPACITY, STREAM_CAPACITY,
+};
+
+/// How long to retry when all cabins or the channel are busy, because
+/// server releases them shortly after previous client disconnects.
+const BUSY_RETRY_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[inline]
+fn retry_busy<R, F: FnMut() -> Result<R>>(mut f: F) -> Result<R> {
+    let inst = Instant::now();
+    loop {
+        match f() {
+            Err(Error::NoFreeCabin | Error::ChannelBusy) if inst.elapsed() < BUSY_RETRY_TIMEOUT => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Options to establish connections, mostly used by shared memory.
+#[derive(Debug, Clone, Copy)]
+pub struct TransportOpts {
+    /// Never yield when waiting for peer.
+    pub spin_only: bool,
+    /// Number of cabins created by server.
+    pub slots: usize,
+    pub wait: WaitBackend,
+    /// NUMA node to bind memory created by server.
+    pub node: Option<usize>,
+    /// Let kernel thread poll submission queue of io_uring connections.
+    pub sqpoll: bool,
+}
+
+impl Default for TransportOpts {
+    #[inline]
+    fn default() -> Self {
+        TransportOpts{spin_only: false, slots: 1, wait: WaitBackend::Pthread, node: None, sqpoll: false}
+    }
+}
+
+/// Connection exchanges u64 values with peer.
+pub trait Connection {
+    /// Send a value to peer.
+    fn send(&mut self, value: u64) -> Result<()>;
+
+    /// Receive a value from peer, returns None if peer disconnected.
+    fn recv(&mut self) -> Result<Option<u64>>;
+
+    /// Send header of payload frame followed by the payload.
+    /// By default, payload is packed into values in little endian,
+    /// and last value is padded with zeros.
+    #[inline]
+    fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        self.send(PAYLOAD_FLAG | payload.len() as u64)?;
+        for chunk in payload.chunks(8) {
+            let mut buf = [0u8; 8];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.send(u64::from_le_bytes(buf))?;
+        }
+        Ok(())
+    }
+
+    /// Receive payload after its header is received.
+    #[inline]
+    fn recv_payload(&mut self, payload: &mut [u8]) -> Result<()> {
+        for chunk in payload.chunks_mut(8) {
+            let value = self.recv()?.ok_or(Error::PeerClosed)?;
+            chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    /// Send values queued by previous sends, called when no receive
+    /// follows, e.g. after a value without response.
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Close the connection, so that peer sees disconnection.
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns how many times the connection yielded when waiting for peer.
+    #[inline]
+    fn yields(&self) -> usize {
+        0
+    }
+
+    /// Returns max number of requests which can be in flight without
+    /// reading responses, more requests would block forever.
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        usize::MAX
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        (**self).send(value)
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        (**self).recv()
+    }
+
+    #[inline]
+    fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        (**self).send_frame(payload)
+    }
+
+    #[inline]
+    fn recv_payload(&mut self, payload: &mut [u8]) -> Result<()> {
+        (**self).recv_payload(payload)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        (**self).close()
+    }
+
+    #[inline]
+    fn yields(&self) -> usize {
+        (**self).yields()
+    }
+
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        (**self).max_in_flight()
+    }
+}
+
+/// Listener accepts connections one by one.
+pub trait Listener {
+    type Conn<'a>: Connection where Self: 'a;
+
+    /// Wait for a client, returns the connection and description of peer.
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)>;
+
+    /// Returns NUMA node of each page of shared memory, empty for sockets.
+    #[inline]
+    fn page_nodes(&self) -> Result<Vec<i32>> {
+        Ok(vec![])
+    }
+}
+
+/// Transport establishes connections of one [`ConnKind`](crate::ConnKind).
+pub trait Transport {
+    type Conn: Connection;
+    type Listener: Listener + Send + 'static;
+
+    /// Connect to server, returns the connection and description of local end.
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)>;
+
+    /// Listen at the address, returns listeners which can accept
+    /// concurrently. Sockets have only one listener, shared memory
+    /// has one for each slot.
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>>;
+}
+
+/// StreamConn sends each value as 8 bytes in little endian.
+pub struct StreamConn<S> {
+    stream: S,
+    max_in_flight: usize,
+}
+
+impl<S: Read + Write> StreamConn<S> {
+    #[inline]
+    pub fn new(stream: S) -> Self {
+        StreamConn{stream, max_in_flight: usize::MAX}
+    }
+
+    /// Create connection over stream with bounded buffer, which holds
+    /// at most given number of requests.
+    #[inline]
+    pub fn with_max_in_flight(stream: S, max_in_flight: usize) -> Self {
+        StreamConn{stream, max_in_flight}
+    }
+}
+
+/// Unwrap error of this crate carried by io error of stream, e.g.
+/// [`Error::ClientDead`] from [`ShmStream`].
+#[inline]
+fn stream_error(e: io::Error) -> Error {
+    if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+        return *e.into_inner().unwrap().downcast::<Error>().unwrap()
+    }
+    e.into()
+}
+
+impl<S: Read + Write> Connection for StreamConn<S> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        self.stream.write_all(&value.to_le_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        let mut buf = [0u8; 8];
+        match self.stream.read_exact(&mut buf) {
+            Ok(_) => Ok(Some(u64::from_le_bytes(buf))),
+            Err(e) if matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset) => Ok(None),
+            Err(e) => Err(stream_error(e)),
+        }
+    }
+
+    /// Header and payload are written together.
+    #[inline]
+    fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        let header = (PAYLOAD_FLAG | payload.len() as u64).to_le_bytes();
+        let n = self.stream.write_vectored(&[IoSlice::new(&header), IoSlice::new(payload)])?;
+        // write remaining bytes if partially written
+        if n < header.len() {
+            self.stream.write_all(&header[n..])?;
+            self.stream.write_all(payload)?;
+        } else {
+            self.stream.write_all(&payload[n - header.len()..])?;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    #[inline]
+    fn recv_payload(&mut self, payload: &mut [u8]) -> Result<()> {
+        self.stream.read_exact(payload).map_err(stream_error)
+    }
+
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+}
+
+impl Listener for TcpListener {
+    type Conn<'a> = StreamConn<TcpStream>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let (conn, remote_addr) = TcpListener::accept(self)?;
+        conn.set_nodelay(true)?;
+        Ok((StreamConn::with_max_in_flight(conn, TCP_MAX_IN_FLIGHT), format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+/// TCP coalesces small writes, thousands of requests fit in default buffers.
+const TCP_MAX_IN_FLIGHT: usize = 4096;
+
+/// Unix socket charges each small write with much more than its size
+/// against send buffer, so only hundreds of requests fit in default buffer.
+const UNIX_MAX_IN_FLIGHT: usize = 256;
+
+impl Listener for UnixListener {
+    type Conn<'a> = StreamConn<UnixStream>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let (conn, remote_addr) = UnixListener::accept(self)?;
+        Ok((StreamConn::with_max_in_flight(conn, UNIX_MAX_IN_FLIGHT), format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    type Conn = StreamConn<TcpStream>;
+    type Listener = TcpListener;
+
+    #[inline]
+    fn connect(addr: &str, _opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let conn = TcpStream::connect(addr)?;
+        // each request is a small write, do not wait to coalesce them
+        conn.set_nodelay(true)?;
+        let local_addr = conn.local_addr()?;
+        Ok((StreamConn::with_max_in_flight(conn, TCP_MAX_IN_FLIGHT), format!("local addr {:?}", local_addr)))
+    }
+
+    #[inline]
+    fn listen(addr: &str, _opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        Ok(vec![TcpListener::bind(addr)?])
+    }
+}
+
+pub struct UnixTransport;
+
+impl Transport for UnixTransport {
+    type Conn = StreamConn<UnixStream>;
+    type Listener = UnixListener;
+
+    #[inline]
+    fn connect(addr: &str, _opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let conn = UnixStream::connect(addr)?;
+        let local_addr = conn.local_addr()?;
+        Ok((StreamConn::with_max_in_flight(conn, UNIX_MAX_IN_FLIGHT), format!("local addr {:?}", local_addr)))
+    }
+
+    #[inline]
+    fn listen(addr: &str, _opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        Ok(vec![UnixListener::bind(addr)?])
+    }
+}
+
+/// Socket listener whose connections are driven by io_uring.
+pub struct UringListener<L> {
+    listener: L,
+    sqpoll: bool,
+    spin_only: bool,
+}
+
+impl Listener for UringListener<TcpListener> {
+    type Conn<'a> = UringConn<TcpStream>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let (conn, remote_addr) = self.listener.accept()?;
+        conn.set_nodelay(true)?;
+        Ok((UringConn::new(conn, self.sqpoll, self.spin_only, TCP_MAX_IN_FLIGHT)?, format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+impl Listener for UringListener<UnixListener> {
+    type Conn<'a> = UringConn<UnixStream>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let (conn, remote_addr) = self.listener.accept()?;
+        Ok((UringConn::new(conn, self.sqpoll, self.spin_only, UNIX_MAX_IN_FLIGHT)?, format!("remote addr {:?}", remote_addr)))
+    }
+}
+
+pub struct TcpUringTransport;
+
+impl Transport for TcpUringTransport {
+    type Conn = UringConn<TcpStream>;
+    type Listener = UringListener<TcpListener>;
+
+    #[inline]
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let conn = TcpStream::connect(addr)?;
+        // each request is a small write, do not wait to coalesce them
+        conn.set_nodelay(true)?;
+        let local_addr = conn.local_addr()?;
+        Ok((UringConn::new(conn, opts.sqpoll, opts.spin_only, TCP_MAX_IN_FLIGHT)?, format!("local addr {:?}", local_addr)))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        Ok(vec![UringListener{listener: TcpListener::bind(addr)?, sqpoll: opts.sqpoll, spin_only: opts.spin_only}])
+    }
+}
+
+pub struct UnixUringTransport;
+
+impl Transport for UnixUringTransport {
+    type Conn = UringConn<UnixStream>;
+    type Listener = UringListener<UnixListener>;
+
+    #[inline]
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let conn = UnixStream::connect(addr)?;
+        let local_addr = conn.local_addr()?;
+        Ok((UringConn::new(conn, opts.sqpoll, opts.spin_only, UNIX_MAX_IN_FLIGHT)?, format!("local addr {:?}", local_addr)))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        Ok(vec![UringListener{listener: UnixListener::bind(addr)?, sqpoll: opts.sqpoll, spin_only: opts.spin_only}])
+    }
+}
+
+/// Server replies to request with odd value only, the cabin is handed
+/// back to client without response when next request is received.
+impl<C: Deref<Target = Cabin<u64, u64>>> Connection for CabinServer<C> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        self.reply(value)
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        CabinServer::recv(self)
+    }
+
+    #[inline]
+    fn yields(&self) -> usize {
+        CabinServer::yields(self)
+    }
+}
+
+impl<C: Deref<Target = Cabin<u64, u64>>> Connection for CabinClient<C, u64, u64> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        CabinClient::send(self, value)
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        CabinClient::recv(self).map(Some)
+    }
+
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        self.disconnect()
+    }
+
+    #[inline]
+    fn yields(&self) -> usize {
+        CabinClient::yields(self)
+    }
+
+    /// Cabin holds only one request.
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        1
+    }
+}
+
+impl Listener for CabinServer<ShmCabin<u64, u64>> {
+    type Conn<'a> = &'a mut Self;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let client_id = CabinServer::accept(self)?;
+        let peer = format!("client {} on slot {}", client_id, self.cabin.slot());
+        Ok((self, peer))
+    }
+
+    #[inline]
+    fn page_nodes(&self) -> Result<Vec<i32>> {
+        self.cabin.page_nodes()
+    }
+}
+
+pub struct ShmTransport;
+
+impl Transport for ShmTransport {
+    type Conn = CabinClient<ShmCabin<u64, u64>, u64, u64>;
+    type Listener = CabinServer<ShmCabin<u64, u64>>;
+
+    #[inline]
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let cabin = retry_busy(|| ShmCabin::<u64, u64>::open(addr))?;
+        let client_id: u32 = rand::random();
+        let local = format!("client {} on slot {}, wait backend is {:?}", client_id, cabin.slot(), cabin.backend());
+        let client = CabinClient::connect(cabin, client_id, opts.spin_only)?;
+        client.cabin().set_connected();
+        Ok((client, local))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        let cabins = ShmCabin::<u64, u64>::create(addr, opts.slots, opts.wait, opts.node)?;
+        Ok(cabins.into_iter().map(|cabin| CabinServer::new(cabin, opts.spin_only)).collect())
+    }
+}
+
+/// Capacity of each buffer of `shm-bytes` cabin, holds payload of 4 MiB
+/// with its header.
+pub const SHM_BYTES_CAPACITY: usize = (4 << 20) + 8;
+
+/// Returns the value at start of request or response of byte cabin.
+#[inline]
+fn read_value(buf: &[u8]) -> Result<u64> {
+    let bytes = buf.get(..8).ok_or(Error::TruncatedMessage)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Value or payload frame is written into request buffer of byte cabin,
+/// so each request takes one hand-off regardless of its size.
+#[inline]
+fn send_bytes(client: &mut CabinClient<ShmByteCabin, usize, usize>, header: u64, payload: &[u8]) -> Result<()> {
+    client.wait_idle()?;
+    let cabin = client.cabin().bytes();
+    let len = 8 + payload.len();
+    if len > cabin.capacity() {
+        return Err(Error::PayloadTooLarge(payload.len(), cabin.capacity().saturating_sub(8)))
+    }
+    // client owns request buffer until it is handed over
+    let buf = unsafe { cabin.req_mut() };
+    buf[..8].copy_from_slice(&header.to_le_bytes());
+    buf[8..len].copy_from_slice(payload);
+    client.send(len)
+}
+
+impl Connection for CabinServer<ShmByteCabin> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        // server owns response buffer until reply
+        unsafe { self.cabin.bytes().resp_mut()[..8].copy_from_slice(&value.to_le_bytes()); }
+        self.reply(8)
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        match CabinServer::recv(self)? {
+            Some(_) => read_value(self.cabin.bytes().req()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Payload follows header in the same request.
+    #[inline]
+    fn recv_payload(&mut self, payload: &mut [u8]) -> Result<()> {
+        let req = self.cabin.bytes().req();
+        payload.copy_from_slice(req.get(8..8 + payload.len()).ok_or(Error::TruncatedMessage)?);
+        Ok(())
+    }
+
+    #[inline]
+    fn yields(&self) -> usize {
+        CabinServer::yields(self)
+    }
+}
+
+impl Connection for CabinClient<ShmByteCabin, usize, usize> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        send_bytes(self, value, &[])
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        CabinClient::recv(self)?;
+        read_value(self.cabin().bytes().resp()).map(Some)
+    }
+
+    #[inline]
+    fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        send_bytes(self, PAYLOAD_FLAG | payload.len() as u64, payload)
+    }
+
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        self.disconnect()
+    }
+
+    #[inline]
+    fn yields(&self) -> usize {
+        CabinClient::yields(self)
+    }
+
+    /// Cabin holds only one request.
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        1
+    }
+}
+
+impl Listener for CabinServer<ShmByteCabin> {
+    type Conn<'a> = &'a mut Self;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let client_id = CabinServer::accept(self)?;
+        Ok((self, format!("client {}", client_id)))
+    }
+
+    #[inline]
+    fn page_nodes(&self) -> Result<Vec<i32>> {
+        self.cabin.page_nodes()
+    }
+}
+
+pub struct ShmBytesTransport;
+
+impl Transport for ShmBytesTransport {
+    type Conn = CabinClient<ShmByteCabin, usize, usize>;
+    type Listener = CabinServer<ShmByteCabin>;
+
+    #[inline]
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let cabin = retry_busy(|| ShmByteCabin::open(addr))?;
+        let client_id: u32 = rand::random();
+        let local = format!("client {}, capacity is {}, wait backend is {:?}", client_id, cabin.bytes().capacity(), cabin.backend());
+        let client = CabinClient::connect(cabin, client_id, opts.spin_only)?;
+        client.cabin().set_connected();
+        Ok((client, local))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        let cabin = ShmByteCabin::create(addr, SHM_BYTES_CAPACITY, opts.wait, opts.node)?;
+        Ok(vec![CabinServer::new(cabin, opts.spin_only)])
+    }
+}
+
+/// Client end of ring channel, disconnects when dropped.
+pub struct RingClient(ShmRing<u64, u64>);
+
+impl Connection for RingClient {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        self.0.send_req(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        self.0.recv_resp().map(Some)
+    }
+
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        self.0.disconnect();
+        Ok(())
+    }
+
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        RING_CAPACITY
+    }
+}
+
+impl Drop for RingClient {
+    fn drop(&mut self) {
+        self.0.disconnect();
+    }
+}
+
+/// Server end of ring channel, resets the channel for next client when dropped.
+pub struct RingServer<'a>(&'a RingChannel<u64, u64>);
+
+impl Connection for RingServer<'_> {
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        self.0.send_resp(value);
+        Ok(())
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        self.0.recv_req()
+    }
+}
+
+impl Drop for RingServer<'_> {
+    fn drop(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl Listener for ShmRing<u64, u64> {
+    type Conn<'a> = RingServer<'a>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let client_id = RingChannel::accept(self);
+        Ok((RingServer(self), format!("client {}", client_id)))
+    }
+
+    #[inline]
+    fn page_nodes(&self) -> Result<Vec<i32>> {
+        ShmRing::page_nodes(self)
+    }
+}
+
+pub struct ShmRingTransport;
+
+impl Transport for ShmRingTransport {
+    type Conn = RingClient;
+    type Listener = ShmRing<u64, u64>;
+
+    #[inline]
+    fn connect(addr: &str, _opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let chan = ShmRing::<u64, u64>::open(addr)?;
+        let client_id: u32 = rand::random();
+        retry_busy(|| chan.connect(client_id))?;
+        Ok((RingClient(chan), format!("client {}", client_id)))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        Ok(vec![ShmRing::create(addr, opts.wait, opts.node)?])
+    }
+}
+
+/// Listener of shared memory stream.
+pub struct ShmStreamListener {
+    chan: ShmRing<u8, u8, STREAM_CAPACITY>,
+    spin_only: bool,
+}
+
+impl Listener for ShmStreamListener {
+    type Conn<'a> = StreamConn<ShmStream<&'a RingChannel<u8, u8, STREAM_CAPACITY>>>;
+
+    #[inline]
+    fn accept(&mut self) -> Result<(Self::Conn<'_>, String)> {
+        let client_id = self.chan.accept();
+        Ok((StreamConn::with_max_in_flight(ShmStream::server(&*self.chan, self.spin_only), STREAM_CAPACITY / 8), format!("client {}", client_id)))
+    }
+
+    #[inline]
+    fn page_nodes(&self) -> Result<Vec<i32>> {
+        self.chan.page_nodes()
+    }
+}
+
+pub struct ShmStreamTransport;
+
+impl Transport for ShmStreamTransport {
+    type Conn = StreamConn<ShmStream<ShmRing<u8, u8, STREAM_CAPACITY>>>;
+    type Listener = ShmStreamListener;
+
+    #[inline]
+    fn connect(addr: &str, opts: &TransportOpts) -> Result<(Self::Conn, String)> {
+        let chan = ShmRing::<u8, u8, STREAM_CAPACITY>::open(addr)?;
+        let client_id: u32 = rand::random();
+        retry_busy(|| chan.connect(client_id))?;
+        let local = format!("client {}, wait backend is {:?}", client_id, chan.backend());
+        Ok((StreamConn::with_max_in_flight(ShmStream::client(chan, opts.spin_only), STREAM_CAPACITY / 8), local))
+    }
+
+    #[inline]
+    fn listen(addr: &str, opts: &TransportOpts) -> Result<Vec<Self::Listener>> {
+        let chan = ShmRing::create(addr, opts.wait, opts.node)?;
+        Ok(vec![ShmStreamListener{chan, spin_only: opts.spin_only}])
+    }
+}
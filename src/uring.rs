@@ -0,0 +1,226 @@
+use std::hint;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use io_uring::{opcode, squeue, types, IoUring};
+use crate::{Connection, Error, Result, PAYLOAD_FLAG};
+
+/// Number of entries of submission queue, at most one send and one
+/// receive are in flight.
+const RING_ENTRIES: u32 = 8;
+
+/// How long the kernel polling thread spins before sleeping.
+const SQPOLL_IDLE_MS: u32 = 1000;
+
+/// Size of buffer of bytes received but not consumed.
+const RECV_BUF_SIZE: usize = 64 * 1024;
+
+const SEND_TOKEN: u64 = 0;
+const RECV_TOKEN: u64 = 1;
+
+/// UringConn sends and receives each value as 8 bytes in little endian
+/// like [`StreamConn`](crate::StreamConn), but through io_uring.
+/// Sent values are queued and submitted together with next receive in
+/// one submission, so a window of requests costs one syscall. Values
+/// without response are submitted by flush.
+/// With SQPOLL, a kernel thread polls submission queue, so submission
+/// makes no syscall. Completions are waited by blocking syscall unless
+/// `spin_only` is set, then they are polled by spinning and no syscall
+/// is made in steady state.
+pub struct UringConn<S> {
+    stream: S,
+    ring: IoUring,
+    sqpoll: bool,
+    spin_only: bool,
+    /// Bytes queued to send, and bytes already sent.
+    send_buf: Vec<u8>,
+    sent: usize,
+    /// Bytes received, consumed from `recv_pos` to `recv_len`.
+    recv_buf: Box<[u8]>,
+    recv_pos: usize,
+    recv_len: usize,
+    max_in_flight: usize,
+}
+
+impl<S: AsRawFd> UringConn<S> {
+    #[inline]
+    pub fn new(stream: S, sqpoll: bool, spin_only: bool, max_in_flight: usize) -> Result<Self> {
+        let ring = if sqpoll {
+            IoUring::builder().setup_sqpoll(SQPOLL_IDLE_MS).build(RING_ENTRIES)?
+        } else {
+            IoUring::new(RING_ENTRIES)?
+        };
+        Ok(UringConn{
+            stream,
+            ring,
+            sqpoll,
+            spin_only,
+            send_buf: Vec::new(),
+            sent: 0,
+            recv_buf: vec![0u8; RECV_BUF_SIZE].into_boxed_slice(),
+            recv_pos: 0,
+            recv_len: 0,
+            max_in_flight,
+        })
+    }
+
+    /// Send all queued bytes, and receive once more if `recv` is set.
+    /// Receive is linked after send, so both are in one submission.
+    /// Returns number of bytes received, 0 if peer disconnected.
+    #[inline]
+    fn submit(&mut self, recv: bool) -> Result<usize> {
+        let fd = types::Fd(self.stream.as_raw_fd());
+        if recv && self.recv_pos == self.recv_len {
+            self.recv_pos = 0;
+            self.recv_len = 0;
+        }
+        loop {
+            let mut n = 0;
+            if self.sent < self.send_buf.len() {
+                let buf = &self.send_buf[self.sent..];
+                let mut entry = opcode::Send::new(fd, buf.as_ptr(), buf.len() as u32).build().user_data(SEND_TOKEN);
+                if recv {
+                    // short send cancels linked receive
+                    entry = entry.flags(squeue::Flags::IO_LINK);
+                }
+                self.push(&entry);
+                n += 1;
+            }
+            if recv {
+                let buf = &mut self.recv_buf[self.recv_len..];
+                let entry = opcode::Recv::new(fd, buf.as_mut_ptr(), buf.len() as u32).build().user_data(RECV_TOKEN);
+                self.push(&entry);
+                n += 1;
+            }
+            if n == 0 {
+                return Ok(0)
+            }
+            self.wait(n)?;
+            let mut received = None;
+            for cqe in self.ring.completion() {
+                let res = cqe.result();
+                if res == -libc::ECANCELED {
+                    continue
+                }
+                if res < 0 {
+                    return Err(io::Error::from_raw_os_error(-res).into())
+                }
+                if cqe.user_data() == SEND_TOKEN {
+                    self.sent += res as usize;
+                } else {
+                    self.recv_len += res as usize;
+                    received = Some(res as usize);
+                }
+            }
+            if self.sent == self.send_buf.len() {
+                self.send_buf.clear();
+                self.sent = 0;
+                if !recv {
+                    return Ok(0)
+                }
+                if let Some(n) = received {
+                    return Ok(n)
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, entry: &squeue::Entry) {
+        // queue never holds more than two entries
+        unsafe { self.ring.submission().push(entry).expect("submission queue is full") }
+    }
+
+    /// Submit queued entries and wait for `n` completions.
+    #[inline]
+    fn wait(&mut self, n: usize) -> Result<()> {
+        if self.sqpoll && self.spin_only {
+            // only wakes up kernel thread if it is idle
+            self.ring.submit()?;
+            while self.ring.completion().len() < n {
+                hint::spin_loop();
+            }
+            return Ok(())
+        }
+        // with SQPOLL, the syscall only waits for completions
+        self.ring.submit_and_wait(n)?;
+        Ok(())
+    }
+
+    /// Receive until given number of bytes are buffered, returns false
+    /// if peer disconnected.
+    #[inline]
+    fn fill(&mut self, len: usize) -> Result<bool> {
+        if self.recv_buf.len() - self.recv_pos < len {
+            self.recv_buf.copy_within(self.recv_pos..self.recv_len, 0);
+            self.recv_len -= self.recv_pos;
+            self.recv_pos = 0;
+        }
+        while self.recv_len - self.recv_pos < len {
+            if self.submit(true)? == 0 {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<S: AsRawFd> Connection for UringConn<S> {
+    /// Value is queued until next receive, flush or close.
+    #[inline]
+    fn send(&mut self, value: u64) -> Result<()> {
+        self.send_buf.extend_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn recv(&mut self) -> Result<Option<u64>> {
+        match self.fill(8) {
+            Ok(true) => (),
+            Ok(false) => return Ok(None),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&self.recv_buf[self.recv_pos..self.recv_pos + 8]);
+        self.recv_pos += 8;
+        Ok(Some(u64::from_le_bytes(buf)))
+    }
+
+    /// Header and payload are queued together.
+    #[inline]
+    fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        self.send_buf.extend_from_slice(&(PAYLOAD_FLAG | payload.len() as u64).to_le_bytes());
+        self.send_buf.extend_from_slice(payload);
+        Ok(())
+    }
+
+    #[inline]
+    fn recv_payload(&mut self, payload: &mut [u8]) -> Result<()> {
+        for chunk in payload.chunks_mut(RECV_BUF_SIZE) {
+            if !self.fill(chunk.len())? {
+                return Err(Error::PeerClosed)
+            }
+            chunk.copy_from_slice(&self.recv_buf[self.recv_pos..self.recv_pos + chunk.len()]);
+            self.recv_pos += chunk.len();
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.submit(false)?;
+        Ok(())
+    }
+
+    /// Send values still queued.
+    #[inline]
+    fn close(&mut self) -> Result<()> {
+        self.submit(false)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+}